@@ -0,0 +1,179 @@
+// Compares how many requests per second each I/O backend serves on this
+// machine. Run with `cargo bench --features io-uring` to include io_uring.
+
+use server_from_scratch::http::{
+    file_server::FileServer, response::Response, router::Router, server::HttpServer,
+};
+use server_from_scratch::net::socket::IoBackend;
+use std::{
+    env, fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// One per worker, so no client waits for a free one.
+const CLIENTS: usize = 4;
+const SMALL_REQUESTS: usize = 5000;
+const FILE_REQUESTS: usize = 200;
+const FILE_SIZE: usize = 1024 * 1024;
+
+fn main() -> io::Result<()> {
+    let root = env::temp_dir().join(format!("throughput-{}", std::process::id()));
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("file.bin"), vec![b'x'; FILE_SIZE])?;
+    let root: &'static str = Box::leak(root.to_string_lossy().into_owned().into_boxed_str());
+
+    let backends = vec![
+        IoBackend::Syscalls,
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        IoBackend::IoUring,
+    ];
+
+    for backend in backends {
+        let result = bench(backend, root);
+        if let Err(e) = result {
+            println!("{:?}: failed: {}", backend, e);
+        }
+    }
+
+    fs::remove_dir_all(root)
+}
+
+fn bench(backend: IoBackend, root: &'static str) -> io::Result<()> {
+    let server = HttpServer::builder()
+        .bind("127.0.0.1:0")
+        .threads(CLIENTS)
+        .io_backend(backend)
+        .build()?;
+    let addr = server.local_addrs()?[0];
+    let shutdown = server.shutdown_handle();
+
+    let router = Router::new()
+        .get("/hello", |_req| {
+            Ok(Response::builder().body(b"Hello, world!".to_vec()).into())
+        })
+        .mount("/files", FileServer::new(root));
+
+    thread::scope(|scope| {
+        let server = scope.spawn(|| server.listen_and_serve(router));
+
+        let small = run(backend, "small", addr, "/hello", SMALL_REQUESTS);
+        let file = small.and_then(|_| run(backend, "file", addr, "/files/file.bin", FILE_REQUESTS));
+
+        shutdown.shutdown();
+        server.join().unwrap()?;
+        file
+    })
+}
+
+/// Has `CLIENTS` keep-alive connections request `path` `requests` times
+/// each and prints the requests per second.
+fn run(
+    backend: IoBackend,
+    name: &str,
+    addr: SocketAddr,
+    path: &str,
+    requests: usize,
+) -> io::Result<()> {
+    let now = Instant::now();
+    let bytes = thread::scope(|scope| {
+        let clients: Vec<_> = (0..CLIENTS)
+            .map(|_| scope.spawn(|| client(addr, path, requests)))
+            .collect();
+
+        clients
+            .into_iter()
+            .map(|client| client.join().unwrap())
+            .sum::<io::Result<usize>>()
+    })?;
+    let elapsed = now.elapsed().as_secs_f64();
+
+    println!(
+        "{:?} {}: {:.0} req/s, {:.1} MiB/s",
+        backend,
+        name,
+        (CLIENTS * requests) as f64 / elapsed,
+        bytes as f64 / elapsed / (1024.0 * 1024.0)
+    );
+    Ok(())
+}
+
+/// Sends `requests` requests for `path`, reconnecting whenever the server
+/// closes the connection. Returns the number of body bytes received.
+fn client(addr: SocketAddr, path: &str, requests: usize) -> io::Result<usize> {
+    let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, addr);
+    let mut connection = None;
+    let mut bytes = 0;
+
+    for _ in 0..requests {
+        let reader = match connection.as_mut() {
+            Some(reader) => reader,
+            None => connection.insert(BufReader::new(connect(addr)?)),
+        };
+        reader.get_mut().write_all(request.as_bytes())?;
+
+        let (len, close) = read_response(reader)?;
+        bytes += len;
+        if close {
+            connection = None;
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// The server only starts listening once `listen_and_serve` runs.
+fn connect(addr: SocketAddr) -> io::Result<TcpStream> {
+    let mut attempts = 0;
+    loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => {
+                stream.set_nodelay(true)?;
+                return Ok(stream);
+            }
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused && attempts < 100 => {
+                attempts += 1;
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Reads one response and returns its body length and whether the server
+/// closes the connection after it.
+fn read_response(reader: &mut BufReader<TcpStream>) -> io::Result<(usize, bool)> {
+    let mut len = 0;
+    let mut close = false;
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("HTTP/") && !line.contains(" 200 ") {
+            return Err(io::Error::other(format!("Unexpected response: {}", line)));
+        }
+
+        let (key, val) = match line.split_once(':') {
+            Some((key, val)) => (key.trim().to_lowercase(), val.trim()),
+            None => continue,
+        };
+        match key.as_str() {
+            "content-length" => len = val.parse().map_err(io::Error::other)?,
+            "connection" => close = val.eq_ignore_ascii_case("close"),
+            _ => {}
+        }
+    }
+
+    io::copy(&mut reader.by_ref().take(len as u64), &mut io::sink())?;
+    Ok((len, close))
+}
@@ -0,0 +1,166 @@
+use crate::http::{parser::Limit, request::InvalidHttpRequestError, status::Status};
+use std::{error, fmt, io};
+
+/// Everything that can go wrong while a request is read, handled and
+/// answered. Each kind knows the status the client is answered with, see
+/// `status`.
+#[derive(Debug)]
+pub enum Error {
+    /// The request isn't valid HTTP. `position` is the offset into the
+    /// request, counted from the first byte of its request line, at which
+    /// the problem was found.
+    Parse {
+        reason: InvalidHttpRequestError,
+        position: usize,
+    },
+    /// The request is larger than the server's `Limits` allow.
+    LimitExceeded(Limit),
+    /// Reading from or writing to the client, or a file, failed.
+    Io(io::Error),
+    /// The client took longer than the server's `Timeouts` allow.
+    Timeout,
+    /// A handler turned the request down, answering with `status`.
+    Handler {
+        status: Status,
+        source: Box<dyn error::Error + Send + Sync>,
+    },
+}
+
+impl Error {
+    /// An error a handler returns to have the client answered with
+    /// `status`. `source` is logged, not sent.
+    pub fn handler(status: Status, source: impl Into<Box<dyn error::Error + Send + Sync>>) -> Self {
+        Error::Handler {
+            status,
+            source: source.into(),
+        }
+    }
+
+    /// The status the client is answered with.
+    pub fn status(&self) -> Status {
+        match self {
+            Error::Parse { reason, .. } => reason.status(),
+            Error::LimitExceeded(Limit::RequestLine) => Status::RequestURITooLong,
+            Error::LimitExceeded(Limit::Headers) => Status::RequestHeaderFieldsTooLarge,
+            Error::LimitExceeded(Limit::Body) => Status::RequestEntityTooLarge,
+            Error::Io(_) => Status::InternalServerError,
+            Error::Timeout => Status::RequestTimeout,
+            Error::Handler { status, .. } => *status,
+        }
+    }
+
+    /// What the client is told about the error along with the status, if
+    /// anything. I/O and handler errors are only logged.
+    pub fn detail(&self) -> Option<String> {
+        match self {
+            Error::Parse { .. } | Error::LimitExceeded(_) | Error::Timeout => {
+                Some(self.to_string())
+            }
+            Error::Io(_) | Error::Handler { .. } => None,
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Parse { reason, .. } => Some(reason),
+            Error::Io(e) => Some(e),
+            Error::Handler { source, .. } => Some(source.as_ref()),
+            Error::LimitExceeded(_) | Error::Timeout => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse { reason, position } => write!(f, "{} (at byte {})", reason, position),
+            Error::LimitExceeded(limit) => write!(f, "{}", limit),
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Timeout => write!(f, "Request took too long!"),
+            Error::Handler { status, source } => {
+                write!(f, "Handler answered {}: {}", status.get_code(), source)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// Lets code that deals in `io::Error`s pass an `Error` on. Anything but an
+/// I/O error is wrapped, so it can be taken back out with `downcast`.
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        let kind = match e {
+            Error::Io(e) => return e,
+            Error::Parse { .. } | Error::LimitExceeded(_) => io::ErrorKind::InvalidData,
+            Error::Timeout => io::ErrorKind::TimedOut,
+            Error::Handler { .. } => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+#[cfg(test)]
+mod test_error {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn test_status_should_match_error() {
+        let vals = [
+            (
+                Error::Parse {
+                    reason: InvalidHttpRequestError::UnknownMethod,
+                    position: 0,
+                },
+                501,
+            ),
+            (Error::LimitExceeded(Limit::Body), 413),
+            (Error::Io(io::ErrorKind::BrokenPipe.into()), 500),
+            (Error::Timeout, 408),
+            (Error::handler(Status::NotFound, "No such user!"), 404),
+        ];
+
+        for (err, code) in vals.iter() {
+            assert_eq!(*code, err.status().get_code(), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_detail_should_only_explain_request_errors() {
+        let err = Error::Parse {
+            reason: InvalidHttpRequestError::InvalidHeader,
+            position: 23,
+        };
+        assert_eq!(
+            Some("Invalid header field! (at byte 23)"),
+            err.detail().as_deref()
+        );
+        assert!(Error::Io(io::ErrorKind::BrokenPipe.into())
+            .detail()
+            .is_none());
+        assert!(Error::handler(Status::NotFound, "No such user!")
+            .detail()
+            .is_none());
+    }
+
+    #[test]
+    fn test_error_should_keep_source() {
+        let err = Error::handler(Status::BadGateway, io::Error::other("Upstream down!"));
+        assert_eq!("Upstream down!", err.source().unwrap().to_string());
+
+        let err = io::Error::from(err);
+        assert_eq!(io::ErrorKind::Other, err.kind());
+        let err = err.into_inner().unwrap().downcast::<Error>().unwrap();
+        assert_eq!(Status::BadGateway, err.status());
+
+        let err = Error::from(io::Error::from(io::ErrorKind::TimedOut));
+        assert_eq!(io::ErrorKind::TimedOut, io::Error::from(err).kind());
+    }
+}
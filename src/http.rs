@@ -1,4 +1,10 @@
 pub mod server;
+pub mod builder;
+pub mod router;
+pub mod middleware;
+pub mod shutdown;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+mod event_loop;
 pub mod file_server;
 pub mod request;
 pub mod response;
@@ -7,5 +13,12 @@ pub mod status;
 pub mod header;
 pub mod url;
 pub mod body;
+pub mod conditional;
+pub mod date;
+pub mod range;
+pub mod chunked;
+pub mod parser;
 pub mod version;
 pub mod thread_pool;
+#[cfg(test)]
+mod testing;
@@ -1,17 +1,230 @@
-use std::{io::Error, str::FromStr};
+use crate::net::socket::Socket;
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Error, Read, Seek, SeekFrom, Write},
+    str::FromStr,
+};
+
+/// Size of the pieces a streamed body is read and written in.
+const STREAM_CHUNK_SIZE: usize = 16 * 1024;
+/// Most bytes handed to a single `sendfile(2)` call.
+const SEND_FILE_CHUNK_SIZE: u64 = 1024 * 1024;
+
+enum Inner {
+    Bytes(Vec<u8>),
+    Stream(Box<dyn Read + Send>),
+    File { file: File, offset: u64, len: u64 },
+}
 
-#[derive(Debug)]
 pub struct Body {
-    inner: Vec<u8>,
+    inner: Inner,
 }
 
 impl Body {
     pub fn new(inner: Vec<u8>) -> Self {
-        Self { inner }
+        Self {
+            inner: Inner::Bytes(inner),
+        }
+    }
+
+    /// A body produced lazily from `reader`. Its length is unknown up front,
+    /// so unless a `Content-Length` is set it goes out chunked.
+    pub fn from_reader(reader: impl Read + Send + 'static) -> Self {
+        Self {
+            inner: Inner::Stream(Box::new(reader)),
+        }
     }
 
+    /// A body sent straight from `file`, without reading it into memory.
+    pub fn from_file(file: File) -> io::Result<Self> {
+        let len = file.metadata()?.len();
+        Ok(Body::from_file_range(file, 0, len))
+    }
+
+    /// A body sent from `len` bytes of `file` starting at `offset`.
+    pub fn from_file_range(file: File, offset: u64, len: u64) -> Self {
+        Self {
+            inner: Inner::File { file, offset, len },
+        }
+    }
+
+    /// A body produced lazily from an iterator of byte chunks.
+    pub fn from_chunks<I>(chunks: I) -> Self
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+        I::IntoIter: Send + 'static,
+    {
+        Body::from_reader(ChunksReader {
+            chunks: chunks.into_iter(),
+            current: io::Cursor::new(vec![]),
+        })
+    }
+
+    /// The buffered bytes of the body. Streamed and file bodies have none.
     pub fn get(&self) -> Vec<u8> {
-        self.inner.clone()
+        match &self.inner {
+            Inner::Bytes(bytes) => bytes.clone(),
+            Inner::Stream(_) | Inner::File { .. } => vec![],
+        }
+    }
+
+    /// The length of the body, if it is known without consuming it.
+    pub fn content_length(&self) -> Option<usize> {
+        match &self.inner {
+            Inner::Bytes(bytes) => Some(bytes.len()),
+            Inner::Stream(_) => None,
+            Inner::File { len, .. } => Some(*len as usize),
+        }
+    }
+
+    pub fn is_file(&self) -> bool {
+        matches!(self.inner, Inner::File { .. })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.content_length() == Some(0)
+    }
+
+    /// Appends to a buffered body while a request is parsed. Streamed and
+    /// file bodies can't be extended.
+    pub(super) fn extend(&mut self, bytes: &[u8]) {
+        match &mut self.inner {
+            Inner::Bytes(inner) => inner.extend_from_slice(bytes),
+            Inner::Stream(_) | Inner::File { .. } => panic!("Can't extend a streamed body!"),
+        }
+    }
+
+    /// Writes the body to `writer` as is.
+    pub fn write_to(self, writer: &mut impl Write) -> io::Result<()> {
+        match self.inner {
+            Inner::Bytes(bytes) => writer.write_all(&bytes),
+            Inner::Stream(mut reader) => io::copy(&mut reader, writer).map(|_| ()),
+            Inner::File { file, offset, len } => copy_file(file, offset, len, writer),
+        }
+    }
+
+    /// Writes the body to `socket` as is. File bodies go out through
+    /// `sendfile(2)` and fall back to buffered reads where it isn't supported.
+    pub fn send_to(self, socket: &Socket) -> io::Result<()> {
+        let (file, mut offset, len) = match self.inner {
+            Inner::File { file, offset, len } => (file, offset, len),
+            _ => {
+                let mut writer = io::BufWriter::new(socket);
+                self.write_to(&mut writer)?;
+                return writer.flush();
+            }
+        };
+
+        let (start, end) = (offset, offset + len);
+        while offset < end {
+            let count = (end - offset).min(SEND_FILE_CHUNK_SIZE) as usize;
+            match socket.send_file(&file, &mut offset, count) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if offset == start && is_send_file_unsupported(&e) => {
+                    debug!(
+                        "sendfile not supported, falling back to buffered reads: {}",
+                        e
+                    );
+                    return copy_file(file, offset, len, &mut io::BufWriter::new(socket));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the body to `writer` using chunked transfer-encoding.
+    pub fn write_chunked_to(self, writer: &mut impl Write) -> io::Result<()> {
+        match self.inner {
+            Inner::Bytes(bytes) => {
+                if !bytes.is_empty() {
+                    write_chunk(writer, &bytes)?;
+                }
+            }
+            Inner::Stream(mut reader) => write_chunks(writer, &mut reader)?,
+            Inner::File {
+                mut file,
+                offset,
+                len,
+            } => {
+                file.seek(SeekFrom::Start(offset))?;
+                write_chunks(writer, &mut file.take(len))?;
+            }
+        }
+
+        writer.write_all(b"0\r\n\r\n")
+    }
+}
+
+fn write_chunks(writer: &mut impl Write, reader: &mut impl Read) -> io::Result<()> {
+    let buf = &mut [0; STREAM_CHUNK_SIZE];
+    loop {
+        match reader.read(buf) {
+            Ok(0) => return Ok(()),
+            Ok(read_bytes) => write_chunk(writer, &buf[..read_bytes])?,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn copy_file(mut file: File, offset: u64, len: u64, writer: &mut impl Write) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    let copied = io::copy(&mut file.take(len), writer)?;
+    if copied < len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    writer.flush()
+}
+
+fn is_send_file_unsupported(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::Unsupported
+        || err.raw_os_error() == Some(nix::errno::Errno::EINVAL as i32)
+        || err.raw_os_error() == Some(nix::errno::Errno::ENOSYS as i32)
+}
+
+fn write_chunk(writer: &mut impl Write, chunk: &[u8]) -> io::Result<()> {
+    writer.write_all(format!("{:X}\r\n", chunk.len()).as_bytes())?;
+    writer.write_all(chunk)?;
+    writer.write_all(b"\r\n")
+}
+
+struct ChunksReader<I> {
+    chunks: I,
+    current: io::Cursor<Vec<u8>>,
+}
+
+impl<I: Iterator<Item = Vec<u8>>> Read for ChunksReader<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read_bytes = self.current.read(buf)?;
+            if read_bytes > 0 || buf.is_empty() {
+                return Ok(read_bytes);
+            }
+
+            match self.chunks.next() {
+                Some(chunk) => self.current = io::Cursor::new(chunk),
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.inner {
+            Inner::Bytes(bytes) => f.debug_struct("Body").field("inner", bytes).finish(),
+            Inner::Stream(_) => f.debug_struct("Body").field("inner", &"<stream>").finish(),
+            Inner::File { offset, len, .. } => f
+                .debug_struct("Body")
+                .field("offset", offset)
+                .field("len", len)
+                .finish(),
+        }
     }
 }
 
@@ -28,3 +241,125 @@ impl FromStr for Body {
         Ok(Body::new(s.to_string().into_bytes()))
     }
 }
+
+#[cfg(test)]
+mod test_write_chunked_to {
+    use super::*;
+
+    fn chunked(body: Body) -> String {
+        let mut out = vec![];
+        body.write_chunked_to(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write_chunked_to_should_end_with_last_chunk() {
+        let vals = [
+            (Body::new(b"hello".to_vec()), "5\r\nhello\r\n0\r\n\r\n"),
+            (Body::default(), "0\r\n\r\n"),
+            (Body::from_chunks(vec![]), "0\r\n\r\n"),
+            (Body::from_reader(io::empty()), "0\r\n\r\n"),
+        ];
+
+        for (body, expected) in vals {
+            assert_eq!(expected, chunked(body));
+        }
+    }
+
+    #[test]
+    fn test_write_chunked_to_should_skip_empty_chunks() {
+        let chunks = vec![
+            vec![],
+            b"ab".to_vec(),
+            vec![],
+            vec![],
+            b"cde".to_vec(),
+            vec![],
+        ];
+
+        assert_eq!(
+            "2\r\nab\r\n3\r\ncde\r\n0\r\n\r\n",
+            chunked(Body::from_chunks(chunks))
+        );
+    }
+
+    #[test]
+    fn test_write_chunked_to_should_split_long_streams() {
+        let body = Body::from_reader(io::Cursor::new(vec![b'x'; STREAM_CHUNK_SIZE + 1]));
+        let expected = format!(
+            "{:X}\r\n{}\r\n1\r\nx\r\n0\r\n\r\n",
+            STREAM_CHUNK_SIZE,
+            "x".repeat(STREAM_CHUNK_SIZE)
+        );
+
+        assert_eq!(expected, chunked(body));
+    }
+}
+
+#[cfg(test)]
+mod test_send_to {
+    use super::*;
+    use nix::fcntl::{fcntl, FcntlArg, OFlag};
+    use std::{env, fs, net::TcpStream, os::unix::io::AsRawFd, thread};
+
+    /// A file larger than one `sendfile(2)` call, with bytes that reveal
+    /// when a piece is sent from the wrong offset.
+    fn large_file(name: &str) -> (File, Vec<u8>) {
+        let path = env::temp_dir().join(format!("body_{}_{}", name, std::process::id()));
+        let contents = (0..3 * SEND_FILE_CHUNK_SIZE + 7)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<u8>>();
+        fs::write(&path, &contents).unwrap();
+
+        let file = File::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        (file, contents)
+    }
+
+    /// Sends `body` from the accepted end of a loopback connection and
+    /// returns what arrives at the other end.
+    fn sent(body: Body, prepare: impl FnOnce(&Socket) + Send + 'static) -> Vec<u8> {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let listener = Socket::new(&addr).unwrap();
+        listener.bind(&addr).unwrap();
+        listener.listen(1).unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+
+        let sender = thread::spawn(move || {
+            let socket = listener.accept().unwrap();
+            prepare(&socket);
+            body.send_to(&socket)
+        });
+
+        let mut received = vec![];
+        client.read_to_end(&mut received).unwrap();
+        sender.join().unwrap().unwrap();
+        received
+    }
+
+    #[test]
+    fn test_send_to_should_send_file_larger_than_one_send_file_call() {
+        let (file, contents) = large_file("sendfile");
+        let len = contents.len() as u64 - 12;
+
+        let received = sent(Body::from_file_range(file, 5, len), |_| {});
+        assert_eq!(len as usize, received.len());
+        assert!(received == contents[5..contents.len() - 7]);
+    }
+
+    #[test]
+    fn test_send_to_should_fall_back_when_send_file_fails() {
+        let (file, contents) = large_file("fallback");
+
+        // sendfile(2) refuses to write to anything opened for appending.
+        let received = sent(Body::from_file(file).unwrap(), |socket| {
+            fcntl(socket.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_APPEND)).unwrap();
+
+            let (probe, _) = large_file("probe");
+            let err = socket.send_file(&probe, &mut 0, 1).unwrap_err();
+            assert!(is_send_file_unsupported(&err), "{}", err);
+        });
+        assert_eq!(contents.len(), received.len());
+        assert!(received == contents);
+    }
+}
@@ -0,0 +1,360 @@
+use super::{
+    parser::Limits,
+    server::{Config, Dispatch, HttpServer, KeepAlive, OverloadPolicy, Timeouts},
+    thread_pool::{PoolConfig, ThreadPool},
+};
+use crate::net::socket::{self, IoBackend, Socket};
+use std::{
+    fs, io,
+    net::{SocketAddr, ToSocketAddrs},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    time,
+};
+
+/// Collects the configuration of an `HttpServer`, see `HttpServer::builder`.
+/// Nothing is bound or started before `build`, which checks every value
+/// first.
+pub struct HttpServerBuilder {
+    addrs: Vec<SocketAddr>,
+    /// Why `bind` couldn't resolve an address, reported by `build`.
+    addr_error: Option<io::Error>,
+    only_v6: bool,
+    unix_sockets: Vec<(PathBuf, u32)>,
+    abstract_names: Vec<String>,
+    pool: PoolConfig,
+    io_backend: IoBackend,
+    config: Config,
+}
+
+impl HttpServerBuilder {
+    pub(super) fn new() -> Self {
+        Self {
+            addrs: vec![],
+            addr_error: None,
+            only_v6: false,
+            unix_sockets: vec![],
+            abstract_names: vec![],
+            pool: PoolConfig::default(),
+            io_backend: IoBackend::default(),
+            config: Config::default(),
+        }
+    }
+
+    /// Listens on every address `addrs` resolves to. Can be called more
+    /// than once, and together with the Unix socket variants.
+    pub fn bind(mut self, addrs: impl ToSocketAddrs) -> Self {
+        match addrs.to_socket_addrs() {
+            Ok(addrs) => self.addrs.extend(addrs),
+            Err(e) => self.addr_error = Some(e),
+        }
+        self
+    }
+
+    /// Makes IPv6 sockets only accept IPv6 clients instead of being
+    /// dual-stack. This allows binding `0.0.0.0` and `::` on the same port.
+    pub fn only_v6(mut self, only_v6: bool) -> Self {
+        self.only_v6 = only_v6;
+        self
+    }
+
+    /// Listens on a Unix domain socket at `path`, with the socket file's
+    /// permission bits set to `mode`.
+    pub fn bind_unix(mut self, path: impl AsRef<Path>, mode: u32) -> Self {
+        self.unix_sockets.push((path.as_ref().to_path_buf(), mode));
+        self
+    }
+
+    /// Listens on a Unix domain socket in the abstract namespace.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub fn bind_abstract(mut self, name: &str) -> Self {
+        self.abstract_names.push(name.to_string());
+        self
+    }
+
+    /// How many connections the kernel queues while none is accepted.
+    /// Defaults to 128.
+    pub fn backlog(mut self, backlog: usize) -> Self {
+        self.config.backlog = backlog;
+        self
+    }
+
+    /// Size of the buffer every connection reads into. Defaults to 30000
+    /// bytes.
+    pub fn read_buffer_size(mut self, size: usize) -> Self {
+        self.config.read_buffer_size = size;
+        self
+    }
+
+    /// Runs exactly `count` workers.
+    pub fn threads(mut self, count: usize) -> Self {
+        self.pool.min_workers = count;
+        self.pool.max_workers = count;
+        self
+    }
+
+    /// Sets up the worker pool, replacing what `threads` did.
+    pub fn pool(mut self, pool: PoolConfig) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    pub fn keep_alive(mut self, keep_alive: KeepAlive) -> Self {
+        self.config.keep_alive = keep_alive;
+        self
+    }
+
+    /// Limits how long clients may take to send a request or to take the
+    /// response.
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.config.timeouts = timeouts;
+        self
+    }
+
+    /// Caps the size of request lines, heads and bodies.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.config.limits = limits;
+        self
+    }
+
+    /// Sent as the `Server` header of every response. None is sent by
+    /// default.
+    pub fn server_name(mut self, name: &str) -> Self {
+        self.config.server_name = Some(name.to_string());
+        self
+    }
+
+    pub fn dispatch(mut self, dispatch: Dispatch) -> Self {
+        self.config.dispatch = dispatch;
+        self
+    }
+
+    pub fn overload_policy(mut self, policy: OverloadPolicy) -> Self {
+        self.config.overload_policy = policy;
+        self
+    }
+
+    /// Makes the listeners, and every connection they accept, go through
+    /// `backend` for their I/O.
+    pub fn io_backend(mut self, backend: IoBackend) -> Self {
+        self.io_backend = backend;
+        self
+    }
+
+    /// How long a shutdown waits for requests in flight before their
+    /// connections are cut off. Defaults to 30 seconds.
+    pub fn shutdown_timeout(mut self, timeout: time::Duration) -> Self {
+        self.config.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Checks the configuration, binds every address and starts the
+    /// workers. Bad values are reported as `io::ErrorKind::InvalidInput`.
+    pub fn build(self) -> io::Result<HttpServer> {
+        if let Some(e) = self.addr_error {
+            return Err(e);
+        }
+        self.validate()?;
+
+        let mut socket_files = vec![];
+        match self.start(&mut socket_files) {
+            Ok((listeners, pool)) => Ok(HttpServer::with_parts(
+                listeners,
+                socket_files,
+                pool,
+                self.config,
+            )),
+            Err(e) => {
+                for path in socket_files.iter() {
+                    let _ = fs::remove_file(path);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Binds every listener and starts the pool. Each Unix socket file is
+    /// added to `socket_files` as soon as it exists, so `build` can remove
+    /// them again when a later step fails.
+    fn start(&self, socket_files: &mut Vec<PathBuf>) -> io::Result<(Vec<Socket>, ThreadPool)> {
+        let mut listeners = vec![];
+
+        for addr in self.addrs.iter() {
+            let socket = Socket::new(addr)?;
+            if addr.is_ipv6() {
+                socket.set_only_v6(self.only_v6)?;
+            }
+            socket.bind(addr)?;
+            listeners.push(socket);
+        }
+
+        for (path, mode) in self.unix_sockets.iter() {
+            socket::remove_stale_unix_socket(path)?;
+
+            let socket = Socket::new_unix()?;
+            socket.bind_unix(path)?;
+            socket_files.push(path.clone());
+            listeners.push(socket);
+            fs::set_permissions(path, fs::Permissions::from_mode(*mode))?;
+        }
+
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        for name in self.abstract_names.iter() {
+            let socket = Socket::new_unix()?;
+            socket.bind_abstract(name.as_bytes())?;
+            listeners.push(socket);
+        }
+
+        for listener in listeners.iter_mut() {
+            listener.set_backend(self.io_backend)?;
+        }
+
+        let pool = ThreadPool::with_config(self.pool.clone())?;
+        Ok((listeners, pool))
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        let config = &self.config;
+        let server_name = config.server_name.as_deref().unwrap_or("-");
+
+        let problem = if self.addrs.is_empty()
+            && self.unix_sockets.is_empty()
+            && self.abstract_names.is_empty()
+        {
+            "no address to bind to"
+        } else if config.backlog == 0 || config.backlog > i32::MAX as usize {
+            "backlog has to be between 1 and i32::MAX"
+        } else if config.read_buffer_size == 0 {
+            "read_buffer_size has to be at least 1"
+        } else if config.keep_alive.timeout.is_zero() {
+            "keep_alive.timeout can't be zero"
+        } else if config.keep_alive.max_requests == 0 {
+            "keep_alive.max_requests has to be at least 1"
+        } else if config.timeouts.header.is_zero()
+            || config.timeouts.body.is_zero()
+            || config.timeouts.write.is_zero()
+        {
+            "timeouts can't be zero"
+        } else if config.limits.max_request_line == 0 {
+            "limits.max_request_line has to be at least 1"
+        } else if config.limits.max_head_size < config.limits.max_request_line {
+            "limits.max_head_size can't be less than limits.max_request_line"
+        } else if server_name.is_empty() || server_name.bytes().any(|b| b.is_ascii_control()) {
+            "server_name has to be non-empty and free of control characters"
+        } else {
+            return self.pool.validate();
+        };
+
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid server config: {}!", problem),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test_builder {
+    use super::*;
+    use std::env;
+
+    fn build_error(builder: HttpServerBuilder) -> String {
+        match builder.build() {
+            Ok(_) => panic!("Config should be invalid!"),
+            Err(e) => {
+                assert_eq!(io::ErrorKind::InvalidInput, e.kind());
+                e.to_string()
+            }
+        }
+    }
+
+    #[test]
+    fn test_builder_should_build_server() {
+        let server = HttpServer::builder()
+            .bind("127.0.0.1:0")
+            .threads(2)
+            .backlog(16)
+            .server_name("test")
+            .build()
+            .unwrap();
+
+        let addrs = server.local_addrs().unwrap();
+        assert_eq!(1, addrs.len());
+        assert_ne!(0, addrs[0].port());
+        assert_eq!(2, server.pool_size());
+    }
+
+    #[test]
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    fn test_builder_should_serve_over_io_uring() {
+        use crate::{
+            error::Error,
+            http::{request::Request, response::Response, testing},
+        };
+
+        // Nothing to test where the kernel lacks io_uring or forbids it.
+        if io_uring::IoUring::new(2).is_err() {
+            return;
+        }
+
+        let echo = |req: Request| -> Result<Response, Error> {
+            Ok(Response::builder().body(req.body.get()).into())
+        };
+        let server = testing::serve(HttpServer::builder().io_backend(IoBackend::IoUring), echo);
+
+        let res = server
+            .exchange("POST / HTTP/1.1\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello");
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{}", res);
+        assert!(res.ends_with("\r\n\r\nhello"), "{}", res);
+    }
+
+    #[test]
+    fn test_builder_should_remove_socket_files_when_failing() {
+        let path = env::temp_dir().join(format!("builder_{}.sock", std::process::id()));
+        let missing = env::temp_dir().join("builder_missing_dir").join("s.sock");
+
+        let err = HttpServer::builder()
+            .bind_unix(&path, 0o600)
+            .bind_unix(&missing, 0o600)
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_builder_should_reject_invalid_config() {
+        let bind = || HttpServer::builder().bind("127.0.0.1:0");
+        let vals = [
+            (HttpServer::builder(), "no address to bind to"),
+            (bind().backlog(0), "backlog"),
+            (bind().read_buffer_size(0), "read_buffer_size"),
+            (bind().threads(0), "max_workers"),
+            (bind().server_name("a\r\nb"), "server_name"),
+            (
+                bind().keep_alive(KeepAlive {
+                    max_requests: 0,
+                    ..KeepAlive::default()
+                }),
+                "keep_alive.max_requests",
+            ),
+            (
+                bind().timeouts(Timeouts {
+                    body: time::Duration::ZERO,
+                    ..Timeouts::default()
+                }),
+                "timeouts",
+            ),
+        ];
+
+        for (builder, expected) in vals {
+            let err = build_error(builder);
+            assert!(
+                err.contains(expected),
+                "{} should mention {}",
+                err,
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use super::{
+    body::Body,
+    header::Header,
+    parser::{parse_fields, Limit},
+    request::InvalidHttpRequestError,
+};
+use crate::error::Error;
+use std::str;
+
+/// Longest accepted chunk size line, extensions included.
+const MAX_CHUNK_LINE_SIZE: usize = 4096;
+/// Largest accepted trailer section.
+const MAX_TRAILER_SIZE: usize = 8192;
+
+#[derive(Debug)]
+enum ChunkState {
+    Size,
+    Data(usize),
+    DataEnd,
+    Trailer,
+}
+
+/// Incremental decoder for `Transfer-Encoding: chunked` request bodies.
+///
+/// Chunk extensions are ignored, trailer fields are collected separately so
+/// they can't override the request header.
+#[derive(Debug)]
+pub struct ChunkedDecoder {
+    state: ChunkState,
+    max_body_size: usize,
+    decoded: usize,
+    /// Bytes taken from the buffer so far, chunk size lines included.
+    consumed: usize,
+    trailer_lines: Vec<String>,
+    trailer_size: usize,
+}
+
+impl ChunkedDecoder {
+    pub fn new(max_body_size: usize) -> Self {
+        Self {
+            state: ChunkState::Size,
+            max_body_size,
+            decoded: 0,
+            consumed: 0,
+            trailer_lines: vec![],
+            trailer_size: 0,
+        }
+    }
+
+    /// Consumes as much of `buffer` as possible, appending chunk data to
+    /// `body`. Returns the trailer once the last chunk was read and `None`
+    /// while more bytes are needed. Error positions count from the start of
+    /// the body.
+    pub fn decode(
+        &mut self,
+        buffer: &mut Vec<u8>,
+        body: &mut Body,
+    ) -> Result<Option<Header>, Error> {
+        loop {
+            match self.state {
+                ChunkState::Size => {
+                    let line_start = self.consumed;
+                    let line = match self.take_line(buffer, MAX_CHUNK_LINE_SIZE)? {
+                        Some(line) => line,
+                        None => return Ok(None),
+                    };
+
+                    let size = parse_chunk_size(&line).map_err(|reason| Error::Parse {
+                        reason,
+                        position: line_start,
+                    })?;
+                    if size > self.max_body_size - self.decoded {
+                        return Err(Error::LimitExceeded(Limit::Body));
+                    }
+
+                    self.state = if size == 0 {
+                        ChunkState::Trailer
+                    } else {
+                        ChunkState::Data(size)
+                    };
+                }
+                ChunkState::Data(remaining) => {
+                    let take = remaining.min(buffer.len());
+                    body.extend(&buffer[..take]);
+                    buffer.drain(..take);
+                    self.decoded += take;
+                    self.consumed += take;
+
+                    if take < remaining {
+                        self.state = ChunkState::Data(remaining - take);
+                        return Ok(None);
+                    }
+
+                    self.state = ChunkState::DataEnd;
+                }
+                ChunkState::DataEnd => {
+                    if buffer.len() < 2 {
+                        return Ok(None);
+                    }
+                    if &buffer[..2] != b"\r\n" {
+                        return Err(Error::Parse {
+                            reason: InvalidHttpRequestError::InvalidChunk,
+                            position: self.consumed,
+                        });
+                    }
+
+                    buffer.drain(..2);
+                    self.consumed += 2;
+                    self.state = ChunkState::Size;
+                }
+                ChunkState::Trailer => {
+                    let trailer_start = self.consumed - self.trailer_size;
+                    let limit = MAX_TRAILER_SIZE.saturating_sub(self.trailer_size);
+                    let line = match self.take_line(buffer, limit)? {
+                        Some(line) => line,
+                        None => return Ok(None),
+                    };
+
+                    if line.is_empty() {
+                        let lines = self
+                            .trailer_lines
+                            .iter()
+                            .map(|l| &l[..])
+                            .collect::<Vec<_>>();
+                        return parse_fields(&lines).map(Some).map_err(|line| {
+                            let offset = lines[..line].iter().map(|l| l.len() + 2).sum::<usize>();
+                            Error::Parse {
+                                reason: InvalidHttpRequestError::InvalidHeader,
+                                position: trailer_start + offset,
+                            }
+                        });
+                    }
+
+                    self.trailer_size += line.len() + 2;
+                    self.trailer_lines.push(line);
+                }
+            }
+        }
+    }
+
+    fn take_line(
+        &mut self,
+        buffer: &mut Vec<u8>,
+        max_size: usize,
+    ) -> Result<Option<String>, Error> {
+        let line = take_line(buffer, max_size).map_err(|reason| Error::Parse {
+            reason,
+            position: self.consumed,
+        })?;
+
+        if let Some(line) = line.as_ref() {
+            self.consumed += line.len() + 2;
+        }
+        Ok(line)
+    }
+}
+
+/// Removes a CRLF terminated line from the front of `buffer`.
+fn take_line(
+    buffer: &mut Vec<u8>,
+    max_size: usize,
+) -> Result<Option<String>, InvalidHttpRequestError> {
+    let end = match buffer.windows(2).position(|window| window == b"\r\n") {
+        Some(end) => end,
+        None if buffer.len() > max_size => return Err(InvalidHttpRequestError::InvalidChunk),
+        None => return Ok(None),
+    };
+
+    if end > max_size {
+        return Err(InvalidHttpRequestError::InvalidChunk);
+    }
+
+    let line = str::from_utf8(&buffer[..end])
+        .map_err(|_| InvalidHttpRequestError::InvalidChunk)?
+        .to_string();
+    buffer.drain(..end + 2);
+
+    Ok(Some(line))
+}
+
+fn parse_chunk_size(line: &str) -> Result<usize, InvalidHttpRequestError> {
+    let size = line.split(';').next().unwrap_or("").trim();
+
+    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(InvalidHttpRequestError::InvalidChunk);
+    }
+
+    usize::from_str_radix(size, 16).map_err(|_| InvalidHttpRequestError::InvalidChunk)
+}
+
+#[cfg(test)]
+mod test_decode {
+    use super::*;
+
+    fn decode_all(input: &[u8], max_body_size: usize) -> Result<(Vec<u8>, Header), Error> {
+        let mut decoder = ChunkedDecoder::new(max_body_size);
+        let mut buffer = input.to_vec();
+        let mut body = Body::default();
+
+        match decoder.decode(&mut buffer, &mut body)? {
+            Some(trailer) => Ok((body.get(), trailer)),
+            None => panic!("Body should be complete!"),
+        }
+    }
+
+    #[test]
+    fn test_decode_should_join_chunks() {
+        let (body, trailer) = decode_all(
+            b"4\r\nWiki\r\n5;name=value\r\npedia\r\nE\r\n in\r\n\r\nchunks.\r\n0\r\n\r\n",
+            1024,
+        )
+        .unwrap();
+
+        assert_eq!(b"Wikipedia in\r\n\r\nchunks.".to_vec(), body);
+        assert_eq!(None, trailer.get("Expires"));
+    }
+
+    #[test]
+    fn test_decode_should_collect_trailer() {
+        let (body, trailer) = decode_all(
+            b"3\r\nabc\r\n0\r\nExpires: never\r\nChecksum: 42\r\n\r\n",
+            1024,
+        )
+        .unwrap();
+
+        assert_eq!(b"abc".to_vec(), body);
+        assert_eq!(Some(String::from("never")), trailer.get("Expires"));
+        assert_eq!(Some(String::from("42")), trailer.get("Checksum"));
+    }
+
+    #[test]
+    fn test_decode_should_wait_for_more_bytes() {
+        let input = b"3\r\nabc\r\n0\r\n\r\n";
+        let mut decoder = ChunkedDecoder::new(1024);
+        let mut buffer = vec![];
+        let mut body = Body::default();
+
+        for byte in &input[..input.len() - 1] {
+            buffer.push(*byte);
+            assert!(decoder.decode(&mut buffer, &mut body).unwrap().is_none());
+        }
+
+        buffer.push(input[input.len() - 1]);
+        assert!(decoder.decode(&mut buffer, &mut body).unwrap().is_some());
+        assert_eq!(b"abc".to_vec(), body.get());
+    }
+
+    #[test]
+    fn test_decode_should_return_error_on_invalid_input() {
+        let vals: [&[u8]; 5] = [
+            b"x\r\nabc\r\n0\r\n\r\n",
+            b"\r\nabc\r\n0\r\n\r\n",
+            b"3\r\nabcd\r\n0\r\n\r\n",
+            b"-3\r\nabc\r\n0\r\n\r\n",
+            b"ffffffffffffffffffff\r\n",
+        ];
+
+        for val in vals.iter() {
+            let mut buffer = val.to_vec();
+            let result = ChunkedDecoder::new(1024).decode(&mut buffer, &mut Body::default());
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn test_decode_should_point_at_invalid_trailer_field() {
+        match decode_all(b"0\r\nExpires: never\r\nBad Name: x\r\n\r\n", 1024) {
+            Err(Error::Parse { reason, position }) => {
+                assert_eq!(
+                    (InvalidHttpRequestError::InvalidHeader, 19),
+                    (reason, position)
+                )
+            }
+            result => panic!("Trailer shouldn't parse, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_decode_should_enforce_size_limits() {
+        assert!(decode_all(b"5\r\nhello\r\n0\r\n\r\n", 4).is_err());
+        assert!(decode_all(b"3\r\nhel\r\n3\r\nlo!\r\n0\r\n\r\n", 5).is_err());
+
+        let long_extension = format!("1;{}\r\n", "x".repeat(MAX_CHUNK_LINE_SIZE));
+        assert!(decode_all(long_extension.as_bytes(), 1024).is_err());
+    }
+}
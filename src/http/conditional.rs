@@ -0,0 +1,175 @@
+use super::{date::parse_http_date, method::Method, request::Request};
+use std::time::SystemTime;
+
+/// Outcome of evaluating a request's preconditions.
+#[derive(Debug, PartialEq)]
+pub enum Precondition {
+    /// Serve the request normally.
+    Proceed,
+    /// Answer with `304 Not Modified`.
+    NotModified,
+    /// Answer with `412 Precondition Failed`.
+    Failed,
+}
+
+/// Evaluates `If-Match`, `If-Unmodified-Since`, `If-None-Match` and
+/// `If-Modified-Since` against the current validators of a representation,
+/// in the order given by RFC 7232, 6.
+pub fn evaluate(req: &Request, etag: &str, last_modified: SystemTime) -> Precondition {
+    let is_get_or_head = matches!(req.method, Method::GET | Method::HEAD);
+
+    if let Some(tags) = req.header.list("If-Match") {
+        if !tags.iter().any(|tag| tag == "*" || strong_eq(tag, etag)) {
+            return Precondition::Failed;
+        }
+    } else if let Some(since) = date(req, "If-Unmodified-Since") {
+        if last_modified > since {
+            return Precondition::Failed;
+        }
+    }
+
+    if let Some(tags) = req.header.list("If-None-Match") {
+        if tags.iter().any(|tag| tag == "*" || weak_eq(tag, etag)) {
+            return if is_get_or_head {
+                Precondition::NotModified
+            } else {
+                Precondition::Failed
+            };
+        }
+    } else if let Some(since) = date(req, "If-Modified-Since") {
+        if is_get_or_head && last_modified <= since {
+            return Precondition::NotModified;
+        }
+    }
+
+    Precondition::Proceed
+}
+
+/// Strong comparison: both tags are strong and identical (RFC 7232, 2.3.2).
+pub fn strong_eq(a: &str, b: &str) -> bool {
+    !a.starts_with("W/") && !b.starts_with("W/") && a == b
+}
+
+/// Weak comparison: opaque tags match, whether weak or not.
+pub fn weak_eq(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
+
+/// Invalid dates make the header count as absent.
+fn date(req: &Request, key: &str) -> Option<SystemTime> {
+    parse_http_date(&req.header.combined(key)?)
+}
+
+#[cfg(test)]
+mod test_evaluate {
+    use super::*;
+    use std::{
+        str::FromStr,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    const ETAG: &str = "\"abc\"";
+    const MODIFIED: &str = "Sun, 06 Nov 1994 08:49:37 GMT";
+
+    fn evaluate_request(request_line: &str, headers: &[&str]) -> Precondition {
+        let mut lines = vec![request_line];
+        lines.extend_from_slice(headers);
+        let req = Request::from_str(&format!("{}\r\n\r\n", lines.join("\r\n"))).unwrap();
+        let last_modified = UNIX_EPOCH + Duration::from_secs(784111777);
+
+        evaluate(&req, ETAG, last_modified)
+    }
+
+    fn evaluate_get(headers: &[&str]) -> Precondition {
+        evaluate_request("GET / HTTP/1.1", headers)
+    }
+
+    #[test]
+    fn test_evaluate_without_conditions_should_proceed() {
+        assert_eq!(Precondition::Proceed, evaluate_get(&[]));
+    }
+
+    #[test]
+    fn test_evaluate_if_none_match() {
+        let vals = [
+            ("If-None-Match: \"abc\"", Precondition::NotModified),
+            ("If-None-Match: W/\"abc\"", Precondition::NotModified),
+            ("If-None-Match: \"xyz\", \"abc\"", Precondition::NotModified),
+            ("If-None-Match: *", Precondition::NotModified),
+            ("If-None-Match: \"xyz\"", Precondition::Proceed),
+        ];
+
+        for (header, expected) in vals.iter() {
+            assert_eq!(*expected, evaluate_get(&[header]));
+        }
+        assert_eq!(
+            Precondition::Failed,
+            evaluate_request("PUT / HTTP/1.1", &["If-None-Match: \"abc\""])
+        );
+    }
+
+    #[test]
+    fn test_evaluate_if_match() {
+        let vals = [
+            ("If-Match: \"abc\"", Precondition::Proceed),
+            ("If-Match: *", Precondition::Proceed),
+            ("If-Match: W/\"abc\"", Precondition::Failed),
+            ("If-Match: \"xyz\"", Precondition::Failed),
+        ];
+
+        for (header, expected) in vals.iter() {
+            assert_eq!(*expected, evaluate_get(&[header]));
+        }
+    }
+
+    #[test]
+    fn test_evaluate_dates() {
+        let vals = [
+            (
+                format!("If-Modified-Since: {}", MODIFIED),
+                Precondition::NotModified,
+            ),
+            (
+                "If-Modified-Since: Sat, 05 Nov 1994 08:49:37 GMT".to_string(),
+                Precondition::Proceed,
+            ),
+            (
+                "If-Modified-Since: garbage".to_string(),
+                Precondition::Proceed,
+            ),
+            (
+                format!("If-Unmodified-Since: {}", MODIFIED),
+                Precondition::Proceed,
+            ),
+            (
+                "If-Unmodified-Since: Sat, 05 Nov 1994 08:49:37 GMT".to_string(),
+                Precondition::Failed,
+            ),
+        ];
+
+        for (header, expected) in vals.iter() {
+            assert_eq!(*expected, evaluate_get(&[header]));
+        }
+    }
+
+    #[test]
+    fn test_evaluate_should_follow_precedence() {
+        let if_match_wins = [
+            "If-Match: \"abc\"",
+            "If-Unmodified-Since: Sat, 05 Nov 1994 08:49:37 GMT",
+        ];
+        assert_eq!(Precondition::Proceed, evaluate_get(&if_match_wins));
+
+        let if_none_match_wins = [
+            "If-None-Match: \"xyz\"",
+            "If-Modified-Since: Mon, 07 Nov 1994 08:49:37 GMT",
+        ];
+        assert_eq!(Precondition::Proceed, evaluate_get(&if_none_match_wins));
+
+        let failed_before_not_modified = ["If-Match: \"xyz\"", "If-None-Match: \"abc\""];
+        assert_eq!(
+            Precondition::Failed,
+            evaluate_get(&failed_before_not_modified)
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    convert::TryFrom,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Formats `time` as an IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn format_http_date(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let days = (secs / 86400) as i64;
+    let (year, month, day) = civil_from_days(days);
+    let weekday = ((days + 4) % 7) as usize;
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[weekday],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Parses the three date formats RFC 7231, 7.1.1.1 requires recipients to
+/// accept: IMF-fixdate, RFC 850 and asctime.
+pub fn parse_http_date(s: &str) -> Option<SystemTime> {
+    let tokens = s.split_whitespace().collect::<Vec<&str>>();
+
+    let (day, month, year, time) = match tokens[..] {
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        [_, day, month, year, time, "GMT"] => (day, month, year.parse().ok()?, time),
+        // Sunday, 06-Nov-94 08:49:37 GMT
+        [_, date, time, "GMT"] => {
+            let parts = date.split('-').collect::<Vec<&str>>();
+            if parts.len() != 3 || parts[2].len() != 2 {
+                return None;
+            }
+            let year: i64 = parts[2].parse().ok()?;
+            let year = if year < 70 { 2000 + year } else { 1900 + year };
+            (parts[0], parts[1], year, time)
+        }
+        // Sun Nov  6 08:49:37 1994
+        [_, month, day, time, year] => (day, month, year.parse().ok()?, time),
+        _ => return None,
+    };
+
+    let day: i64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+
+    let time = time
+        .split(':')
+        .map(|t| t.parse::<i64>().ok())
+        .collect::<Option<Vec<i64>>>()?;
+    if time.len() != 3 || time.iter().any(|t| *t < 0) {
+        return None;
+    }
+    if time[0] > 23 || time[1] > 59 || time[2] > 60 {
+        return None;
+    }
+    // Four digit years are all the formats allow.
+    if !(1..=31).contains(&day) || !(1970..=9999).contains(&year) {
+        return None;
+    }
+
+    let secs = days_from_civil(year, month, day)
+        .checked_mul(86400)?
+        .checked_add(time[0] * 3600 + time[1] * 60 + time[2])?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+/// Converts days since the epoch to a (year, month, day) date.
+/// See http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod test_http_date {
+    use super::*;
+
+    #[test]
+    fn test_format_http_date() {
+        let time = UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!("Sun, 06 Nov 1994 08:49:37 GMT", format_http_date(time));
+        assert_eq!(
+            "Thu, 01 Jan 1970 00:00:00 GMT",
+            format_http_date(UNIX_EPOCH)
+        );
+    }
+
+    #[test]
+    fn test_parse_http_date_should_accept_all_formats() {
+        let expected = Some(UNIX_EPOCH + Duration::from_secs(784111777));
+        let vals = [
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+        ];
+
+        for val in vals.iter() {
+            assert_eq!(expected, parse_http_date(val));
+        }
+    }
+
+    #[test]
+    fn test_parse_http_date_should_round_trip() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(Some(time), parse_http_date(&format_http_date(time)));
+    }
+
+    #[test]
+    fn test_parse_http_date_should_return_none_on_invalid_input() {
+        let vals = [
+            "",
+            "yesterday",
+            "Sun, 06 Nov 1994 08:49:37 CET",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 25:49:37 GMT",
+            "Sun, 06 Nov 1994 08:49 GMT",
+            "Sun, 06 Nov 1994 08:-1:37 GMT",
+            "Sun, 06 Nov 1969 08:49:37 GMT",
+        ];
+
+        for val in vals.iter() {
+            assert_eq!(None, parse_http_date(val));
+        }
+    }
+
+    #[test]
+    fn test_parse_http_date_should_return_none_on_huge_year() {
+        let vals = [
+            "Sun, 06 Nov 10000 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 9223372036854775807",
+        ];
+
+        for val in vals.iter() {
+            assert_eq!(None, parse_http_date(val));
+        }
+    }
+}
@@ -0,0 +1,606 @@
+use super::{
+    parser::{ParseStatus, RequestParser},
+    request::Request,
+    server::{Config, Handler, HttpServer, Overload},
+    shutdown::{Shutdown, TrackedSocket},
+    thread_pool::{Job, ThreadPool},
+    version::Version,
+};
+use crate::{
+    error::Error,
+    net::socket::{PeerCredentials, Socket},
+};
+use nix::{
+    sys::{
+        epoll::{
+            epoll_create1, epoll_ctl, epoll_wait, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp,
+        },
+        eventfd::{eventfd, EfdFlags},
+    },
+    unistd::{close, read, write},
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    os::unix::io::{AsRawFd, RawFd},
+    sync::{mpsc, Arc},
+    time::{Duration, Instant},
+};
+
+/// Longest `epoll_wait` sleep, so timeouts are noticed without any events.
+const TICK: Duration = Duration::from_millis(500);
+/// `epoll_wait` sleep while jobs are parked, waiting for the pool to take
+/// them.
+const PARKED_TICK: Duration = Duration::from_millis(5);
+/// Most events handled per `epoll_wait` call.
+const MAX_EVENTS: usize = 1024;
+
+/// A keep-alive connection waiting in the event loop for its next request.
+struct Connection {
+    socket: TrackedSocket,
+    parser: RequestParser,
+    served: usize,
+    peer_credentials: Option<PeerCredentials>,
+    /// When the connection is closed, with a 408 if a request was started.
+    /// Set from the `Timeouts` of the part of the request being read.
+    deadline: Instant,
+}
+
+#[allow(clippy::large_enum_variant)]
+enum Readiness {
+    /// Nothing complete yet, keep watching the socket.
+    Pending,
+    Request(Request),
+    Malformed(Error),
+    Closed,
+}
+
+/// Watches the listeners and every idle or partially read connection with
+/// epoll on the calling thread. Only complete requests are handed to the
+/// pool, and connections come back once their response was sent, so a
+/// worker is never stuck waiting on a slow or idle client. Malformed
+/// requests are answered by the loop itself. The loop never waits for the
+/// pool either: a request that finds it full is shed, and jobs the pool
+/// can't take right away are parked until it can.
+pub(super) fn run(
+    listeners: &[Socket],
+    pool: &ThreadPool,
+    overload: &Overload,
+    handler: impl Handler,
+    config: &Arc<Config>,
+    shutdown: &Shutdown,
+) -> io::Result<()> {
+    let epoll = Epoll::new()?;
+    let waker = Arc::new(Waker::new()?);
+    let (returns, returned) = mpsc::channel::<Connection>();
+
+    for listener in listeners.iter() {
+        listener.set_nonblocking(true)?;
+        epoll.add(listener.as_raw_fd())?;
+    }
+    epoll.add(waker.fd)?;
+
+    let mut connections: HashMap<RawFd, Connection> = HashMap::new();
+    let mut parked: VecDeque<Job> = VecDeque::new();
+    let mut events = vec![EpollEvent::empty(); MAX_EVENTS];
+    let mut accepting = true;
+    let read_buffer = &mut vec![0; config.read_buffer_size];
+
+    loop {
+        if let Some(deadline) = shutdown.deadline(config.shutdown_timeout) {
+            if accepting {
+                for listener in listeners.iter() {
+                    epoll.delete(listener.as_raw_fd())?;
+                }
+                connections.retain(|_, connection| !connection.parser.is_idle());
+                accepting = false;
+            }
+            if (connections.is_empty() && parked.is_empty()) || Instant::now() >= deadline {
+                break;
+            }
+        }
+
+        let timeout = if parked.is_empty() { TICK } else { PARKED_TICK };
+        let ready = epoll.wait(&mut events, timeout)?;
+        for event in events[..ready].iter() {
+            let fd = event.data() as RawFd;
+
+            if fd == waker.fd {
+                waker.reset();
+                continue;
+            }
+
+            if let Some(listener) = listeners.iter().find(|l| l.as_raw_fd() == fd) {
+                if accepting {
+                    accept(listener, &epoll, &mut connections, config, shutdown)?;
+                }
+                continue;
+            }
+
+            let connection = match connections.get_mut(&fd) {
+                Some(connection) => connection,
+                None => continue,
+            };
+
+            match read_ready(connection, read_buffer, config) {
+                Readiness::Pending => continue,
+                Readiness::Closed => {
+                    connections.remove(&fd);
+                }
+                Readiness::Malformed(e) => {
+                    let connection = connections.remove(&fd).unwrap();
+                    let _ = HttpServer::reject(&connection.socket, &e, Version::default(), config);
+                }
+                Readiness::Request(_)
+                    if overload.shed_nonblocking(&connections[&fd].socket, pool, config) =>
+                {
+                    connections.remove(&fd);
+                }
+                Readiness::Request(req) => {
+                    let connection = connections.remove(&fd).unwrap();
+                    if let Err(e) = epoll.delete(fd) {
+                        warn!("Dropping connection {}: {}", fd, e);
+                        continue;
+                    }
+
+                    let handler = handler.clone();
+                    let config = Arc::clone(config);
+                    let shutdown = shutdown.clone();
+                    let returns = returns.clone();
+                    let waker = Arc::clone(&waker);
+                    parked.push_back(Box::new(move || {
+                        let connection = match serve(connection, req, &handler, &config, &shutdown)?
+                        {
+                            Some(connection) => connection,
+                            None => return Ok(()),
+                        };
+                        if returns.send(connection).is_ok() {
+                            waker.wake();
+                        }
+                        Ok(())
+                    }));
+                    dispatch(pool, &mut parked);
+                }
+            }
+        }
+
+        dispatch(pool, &mut parked);
+
+        while let Ok(connection) = returned.try_recv() {
+            let fd = connection.socket.as_raw_fd();
+            if !connection.socket.set_idle(connection.parser.is_idle()) {
+                continue;
+            }
+            if let Err(e) = epoll.add(fd) {
+                warn!("Dropping connection {}: {}", fd, e);
+                continue;
+            }
+            connections.insert(fd, connection);
+        }
+
+        let now = Instant::now();
+        connections.retain(|_, connection| {
+            if connection.deadline > now {
+                return true;
+            }
+            if !connection.parser.is_idle() {
+                let _ = HttpServer::reject(
+                    &connection.socket,
+                    &Error::Timeout,
+                    Version::default(),
+                    config,
+                );
+            }
+            false
+        });
+    }
+
+    Ok(())
+}
+
+/// Hands parked jobs to the pool in order, for as long as it takes them.
+fn dispatch(pool: &ThreadPool, parked: &mut VecDeque<Job>) {
+    while let Some(job) = parked.pop_front() {
+        if let Err(job) = pool.try_execute(job) {
+            parked.push_front(job);
+            return;
+        }
+    }
+}
+
+/// Accepts every pending connection on `listener`.
+fn accept(
+    listener: &Socket,
+    epoll: &Epoll,
+    connections: &mut HashMap<RawFd, Connection>,
+    config: &Config,
+    shutdown: &Shutdown,
+) -> io::Result<()> {
+    loop {
+        let socket = match listener.accept() {
+            Ok(socket) => shutdown.track(socket),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(_) if shutdown.is_requested() => return Ok(()),
+            Err(e) => match HttpServer::check_accept_error(e) {
+                Ok(()) => continue,
+                Err(e) => {
+                    error!("Can't accept on socket {}: {}", listener.as_raw_fd(), e);
+                    shutdown.shutdown();
+                    return Err(e);
+                }
+            },
+        };
+
+        info!("Got a new connection");
+        let peer_credentials = match prepare_socket(&socket, config) {
+            Ok(peer_credentials) => peer_credentials,
+            Err(e) => {
+                warn!("Dropping new connection: {}", e);
+                continue;
+            }
+        };
+
+        let fd = socket.as_raw_fd();
+        if let Err(e) = epoll.add(fd) {
+            warn!("Dropping new connection: {}", e);
+            continue;
+        }
+        connections.insert(
+            fd,
+            Connection {
+                socket,
+                parser: RequestParser::with_limits(config.limits),
+                served: 0,
+                peer_credentials,
+                deadline: Instant::now() + config.timeouts.header,
+            },
+        );
+    }
+}
+
+fn prepare_socket(socket: &Socket, config: &Config) -> io::Result<Option<PeerCredentials>> {
+    let peer_credentials = HttpServer::prepare_socket(socket, config)?;
+    socket.set_nonblocking(true)?;
+    Ok(peer_credentials)
+}
+
+/// Reads whatever the client sent so far, without blocking.
+fn read_ready(connection: &mut Connection, read_buffer: &mut [u8], config: &Config) -> Readiness {
+    loop {
+        let read_bytes = match connection.socket.receive(read_buffer) {
+            Ok(0) if connection.parser.is_idle() => return Readiness::Closed,
+            Ok(0) => return Readiness::Malformed(connection.parser.incomplete()),
+            Ok(read_bytes) => read_bytes,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Readiness::Pending,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                debug!("Closing connection after read error: {}", e);
+                return Readiness::Closed;
+            }
+        };
+
+        if connection.parser.is_idle() {
+            connection.socket.set_idle(false);
+            connection.deadline = Instant::now() + config.timeouts.header;
+        }
+
+        match connection.parser.feed(&read_buffer[..read_bytes]) {
+            Ok(ParseStatus::Complete(req)) => return Readiness::Request(req),
+            Ok(ParseStatus::Incomplete) => {}
+            Err(e) => return Readiness::Malformed(e),
+        }
+
+        if connection.parser.is_reading_body() {
+            connection.deadline = Instant::now() + config.timeouts.body;
+        }
+    }
+}
+
+/// Serves `req` and any requests pipelined behind it on a worker. Returns
+/// the connection if it stays open.
+fn serve(
+    mut connection: Connection,
+    req: Request,
+    handler: &impl Handler,
+    config: &Config,
+    shutdown: &Shutdown,
+) -> io::Result<Option<Connection>> {
+    connection.socket.set_nonblocking(false)?;
+    let mut next = Some(req);
+
+    while let Some(mut req) = next.take() {
+        connection.served += 1;
+        req.peer_credentials = connection.peer_credentials;
+
+        let may_persist = connection.served < config.keep_alive.max_requests;
+        if !HttpServer::serve_request(
+            &connection.socket,
+            handler,
+            req,
+            may_persist,
+            config,
+            shutdown,
+        )? {
+            return Ok(None);
+        }
+
+        match connection.parser.feed(&[]) {
+            Ok(ParseStatus::Complete(req)) => next = Some(req),
+            Ok(ParseStatus::Incomplete) => {}
+            Err(e) => {
+                HttpServer::reject(&connection.socket, &e, Version::default(), config)?;
+                return Ok(None);
+            }
+        }
+    }
+
+    connection.socket.set_nonblocking(true)?;
+    // A pipelined request may already be partly read.
+    let timeout = if connection.parser.is_idle() {
+        config.keep_alive.timeout
+    } else if connection.parser.is_reading_body() {
+        config.timeouts.body
+    } else {
+        config.timeouts.header
+    };
+    connection.deadline = Instant::now() + timeout;
+    Ok(Some(connection))
+}
+
+struct Epoll {
+    fd: RawFd,
+}
+
+impl Epoll {
+    fn new() -> io::Result<Self> {
+        let fd = epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC).map_err(nix_to_io_error)?;
+        Ok(Self { fd })
+    }
+
+    /// Watches `fd` for incoming data, level-triggered.
+    fn add(&self, fd: RawFd) -> io::Result<()> {
+        let mut event = EpollEvent::new(EpollFlags::EPOLLIN | EpollFlags::EPOLLRDHUP, fd as u64);
+        epoll_ctl(self.fd, EpollOp::EpollCtlAdd, fd, &mut event).map_err(nix_to_io_error)
+    }
+
+    fn delete(&self, fd: RawFd) -> io::Result<()> {
+        epoll_ctl(self.fd, EpollOp::EpollCtlDel, fd, None).map_err(nix_to_io_error)
+    }
+
+    fn wait(&self, events: &mut [EpollEvent], timeout: Duration) -> io::Result<usize> {
+        match epoll_wait(self.fd, events, timeout.as_millis() as isize) {
+            Ok(ready) => Ok(ready),
+            Err(err) if err.as_errno() == Some(nix::errno::Errno::EINTR) => Ok(0),
+            Err(err) => Err(nix_to_io_error(err)),
+        }
+    }
+}
+
+impl Drop for Epoll {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
+
+/// An eventfd that wakes up `epoll_wait` when a worker returns a connection.
+struct Waker {
+    fd: RawFd,
+}
+
+impl Waker {
+    fn new() -> io::Result<Self> {
+        let fd =
+            eventfd(0, EfdFlags::EFD_CLOEXEC | EfdFlags::EFD_NONBLOCK).map_err(nix_to_io_error)?;
+        Ok(Self { fd })
+    }
+
+    fn wake(&self) {
+        let _ = write(self.fd, &1u64.to_ne_bytes());
+    }
+
+    fn reset(&self) {
+        let _ = read(self.fd, &mut [0; 8]);
+    }
+}
+
+impl Drop for Waker {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
+
+fn nix_to_io_error(err: nix::Error) -> io::Error {
+    match err.as_errno() {
+        Some(err_num) => io::Error::from_raw_os_error(err_num as i32),
+        None => io::Error::other(err),
+    }
+}
+
+#[cfg(test)]
+mod test_event_loop {
+    use super::*;
+    use crate::http::{
+        builder::HttpServerBuilder,
+        response::Response,
+        server::{Dispatch, KeepAlive, Timeouts},
+        testing,
+        thread_pool::PoolConfig,
+    };
+    use std::{
+        io::{BufReader, Write},
+        sync::Mutex,
+        thread,
+    };
+
+    fn builder() -> HttpServerBuilder {
+        HttpServer::builder().dispatch(Dispatch::EventLoop)
+    }
+
+    fn echo(req: Request) -> Result<Response, Error> {
+        Ok(Response::builder().body(req.url.path.into_bytes()).into())
+    }
+
+    #[test]
+    fn test_event_loop_should_serve_pipelined_requests_in_order() {
+        let server = testing::serve(builder(), echo);
+        let mut stream = server.connect();
+        stream
+            .write_all(b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\nGET /c HTTP/1.1\r\n")
+            .unwrap();
+        let mut reader = BufReader::new(stream);
+
+        assert!(testing::read_response(&mut reader, false).ends_with("\r\n\r\n/a"));
+        assert!(testing::read_response(&mut reader, false).ends_with("\r\n\r\n/b"));
+
+        reader.get_mut().write_all(b"\r\n").unwrap();
+        assert!(testing::read_response(&mut reader, false).ends_with("\r\n\r\n/c"));
+    }
+
+    #[test]
+    fn test_event_loop_should_not_hold_worker_for_idle_connections() {
+        let server = testing::serve(builder().threads(1), echo);
+        let mut readers = (0..3)
+            .map(|_| BufReader::new(server.connect()))
+            .collect::<Vec<_>>();
+
+        for round in 0..2 {
+            for (i, reader) in readers.iter_mut().enumerate() {
+                let path = format!("/{}/{}", round, i);
+                let req = format!("GET {} HTTP/1.1\r\n\r\n", path);
+                reader.get_mut().write_all(req.as_bytes()).unwrap();
+
+                let res = testing::read_response(reader, false);
+                assert!(res.contains("Connection: keep-alive\r\n"), "{}", res);
+                assert!(res.ends_with(&path), "{}", res);
+            }
+        }
+    }
+
+    #[test]
+    fn test_event_loop_should_time_out_started_requests_only() {
+        let timeouts = Timeouts {
+            header: Duration::from_millis(200),
+            ..Timeouts::default()
+        };
+        let keep_alive = KeepAlive {
+            timeout: Duration::from_millis(200),
+            ..KeepAlive::default()
+        };
+        let server = testing::serve(builder().timeouts(timeouts).keep_alive(keep_alive), echo);
+
+        let res = server.exchange("GET / HTTP/1.1\r\nHost: x");
+        assert!(
+            res.starts_with("HTTP/1.1 408 Request Timeout\r\n"),
+            "{}",
+            res
+        );
+
+        let mut idle = BufReader::new(server.connect());
+        idle.get_mut().write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        testing::read_response(&mut idle, false);
+        assert_eq!("", testing::read_to_close(idle.get_mut()));
+
+        assert_eq!("", testing::read_to_close(&mut server.connect()));
+    }
+
+    #[test]
+    fn test_event_loop_should_time_out_partial_pipelined_request() {
+        let timeouts = Timeouts {
+            header: Duration::from_millis(200),
+            ..Timeouts::default()
+        };
+        let keep_alive = KeepAlive {
+            timeout: Duration::from_secs(60),
+            ..KeepAlive::default()
+        };
+        let server = testing::serve(builder().timeouts(timeouts).keep_alive(keep_alive), echo);
+        let mut stream = server.connect();
+        stream
+            .write_all(b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\nHost: x")
+            .unwrap();
+
+        let res = testing::read_to_close(&mut stream);
+        assert!(
+            res.contains("\r\n\r\n/aHTTP/1.1 408 Request Timeout\r\n"),
+            "{}",
+            res
+        );
+    }
+
+    #[test]
+    fn test_event_loop_should_drain_on_shutdown() {
+        let (started, in_flight) = mpsc::channel();
+        let handler = move |req: Request| {
+            if req.url.path == "/slow" {
+                started.send(()).unwrap();
+                thread::sleep(Duration::from_millis(300));
+            }
+            echo(req)
+        };
+        let server = testing::serve(builder().threads(2), handler);
+
+        let mut idle = BufReader::new(server.connect());
+        idle.get_mut()
+            .write_all(b"GET /fast HTTP/1.1\r\n\r\n")
+            .unwrap();
+        testing::read_response(&mut idle, false);
+
+        let mut busy = server.connect();
+        busy.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+        in_flight.recv().unwrap();
+        server.shutdown.shutdown();
+
+        let res = testing::read_to_close(&mut busy);
+        assert!(res.contains("Connection: close\r\n"), "{}", res);
+        assert!(res.ends_with("/slow"), "{}", res);
+        assert_eq!("", testing::read_to_close(idle.get_mut()));
+        server.stop().unwrap();
+    }
+
+    #[test]
+    fn test_event_loop_should_shed_instead_of_blocking_on_full_pool() {
+        let (started, in_flight) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        let released = Arc::new(Mutex::new(released));
+        let handler = move |req: Request| {
+            started.send(()).unwrap();
+            released.lock().unwrap().recv().unwrap();
+            echo(req)
+        };
+        let pool = PoolConfig {
+            min_workers: 1,
+            max_workers: 1,
+            queue_capacity: 1,
+            ..PoolConfig::default()
+        };
+        let server = testing::serve(builder().pool(pool), handler);
+
+        let mut running = server.connect();
+        running
+            .write_all(b"GET /running HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        in_flight.recv().unwrap();
+        let mut queued = server.connect();
+        queued
+            .write_all(b"GET /queued HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        thread::sleep(Duration::from_millis(200));
+
+        let res = server.exchange("GET /shed HTTP/1.1\r\n\r\n");
+        assert!(
+            res.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+            "{}",
+            res
+        );
+        assert!(res.contains("Retry-After: 1\r\n"), "{}", res);
+
+        let res = server.exchange("GET\r\n\r\n");
+        assert!(res.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", res);
+
+        for _ in 0..2 {
+            release.send(()).unwrap();
+        }
+        assert!(testing::read_to_close(&mut running).contains("\r\n\r\n/running"));
+        assert!(testing::read_to_close(&mut queued).contains("\r\n\r\n/queued"));
+    }
+}
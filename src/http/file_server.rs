@@ -1,14 +1,117 @@
-use super::{request::Request, response::Response, server::Handler, status::Status};
-use std::{fs, io, path::Path, str};
+use super::{
+    conditional::{self, Precondition},
+    date::{format_http_date, parse_http_date},
+    method::Method,
+    range::{parse_range, MultipartRanges, Ranges},
+    request::Request,
+    response::{self, Response},
+    server::Handler,
+    status::Status,
+    url::percent_decode,
+};
+use crate::error::Error;
+use std::{
+    ffi::OsStr,
+    fs, io,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+    str,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// How `FileServer` treats symbolic links below its root.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymlinkPolicy {
+    /// Follow every link, even ones pointing outside the root.
+    Follow,
+    /// Follow links as long as their target stays inside the root.
+    FollowWithinRoot,
+    /// Refuse to serve anything reached through a link.
+    Deny,
+}
 
 #[derive(Clone)]
 pub struct FileServer {
     path: &'static str,
+    root: PathBuf,
+    symlinks: SymlinkPolicy,
 }
 
 impl FileServer {
     pub fn new(path: &'static str) -> Self {
-        Self { path }
+        let root = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+
+        Self {
+            path,
+            root,
+            symlinks: SymlinkPolicy::FollowWithinRoot,
+        }
+    }
+
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+}
+
+impl FileServer {
+    /// Maps a request path to a file below the root. The path is split into
+    /// segments before percent-decoding, so an encoded `/` can't introduce
+    /// new segments, and dot segments are resolved without touching the
+    /// file system. Paths climbing above the root are refused.
+    fn resolve(&self, url_path: &str) -> Result<(PathBuf, Vec<Vec<u8>>), Status> {
+        let mut segments: Vec<Vec<u8>> = vec![];
+
+        for segment in url_path.split('/') {
+            let segment = percent_decode(segment).ok_or(Status::BadRequest)?;
+            if segment.contains(&b'/') || segment.contains(&0) {
+                return Err(Status::BadRequest);
+            }
+
+            match &segment[..] {
+                b"" | b"." => {}
+                b".." => {
+                    segments.pop().ok_or(Status::Forbidden)?;
+                }
+                _ => segments.push(segment),
+            }
+        }
+
+        let mut path = self.root.clone();
+        for segment in segments.iter() {
+            path.push(OsStr::from_bytes(segment));
+        }
+
+        let path = match self.symlinks {
+            SymlinkPolicy::Follow => path,
+            SymlinkPolicy::FollowWithinRoot => self.confine(&path)?,
+            SymlinkPolicy::Deny => {
+                let mut prefix = self.root.clone();
+                for segment in segments.iter() {
+                    prefix.push(OsStr::from_bytes(segment));
+                    match fs::symlink_metadata(&prefix) {
+                        Ok(metadata) if metadata.file_type().is_symlink() => {
+                            return Err(Status::Forbidden)
+                        }
+                        Ok(_) => {}
+                        Err(_) => return Err(Status::NotFound),
+                    }
+                }
+                self.confine(&path)?
+            }
+        };
+
+        Ok((path, segments))
+    }
+
+    /// Canonicalizes `path` and makes sure it is still below the root.
+    fn confine(&self, path: &Path) -> Result<PathBuf, Status> {
+        let path = fs::canonicalize(path).map_err(|_| Status::NotFound)?;
+        if !path.starts_with(&self.root) {
+            warn!("Refusing to serve {:?} outside of {:?}", path, self.root);
+            return Err(Status::Forbidden);
+        }
+        Ok(path)
     }
 }
 
@@ -28,7 +131,13 @@ impl FileServer {
         )
     }
 
-    fn build_body(&self, path: String, previous_path: String, filenames: Vec<String>) -> String {
+    fn build_body(
+        &self,
+        base_path: &str,
+        path: String,
+        previous_path: String,
+        filenames: Vec<String>,
+    ) -> String {
         let mut path = &path[..];
         if path == "/" {
             path = "";
@@ -36,13 +145,18 @@ impl FileServer {
 
         let header = format!("<h3>{}{}</h3>", self.path, path);
         let back = if !path.is_empty() {
-            format!("<a href=\"{}\">Back</a>", previous_path)
+            format!("<a href=\"{}{}\">Back</a>", base_path, previous_path)
         } else {
-            format!("")
+            String::new()
         };
         let lis = filenames
             .iter()
-            .map(|filename| format!("<li><a href=\"{}{}\">{}</a></li>", path, filename, filename))
+            .map(|filename| {
+                format!(
+                    "<li><a href=\"{}{}{}\">{}</a></li>",
+                    base_path, path, filename, filename
+                )
+            })
             .collect::<Vec<String>>();
         let ul = format!("<ul>{}</ul>", lis.join(""));
 
@@ -51,38 +165,23 @@ impl FileServer {
 }
 
 impl FileServer {
-    fn get_parent_path(&self, parenth_path: &Path) -> String {
-        let parent_path_str = parenth_path.to_str().unwrap_or("").to_string();
-
-        if parent_path_str.starts_with(self.path) {
-            let parenth_path = parent_path_str
-                .strip_prefix(self.path)
-                .expect("Couldn't trim previous path")
-                .to_string();
-
-            if !parenth_path.is_empty() {
-                return parenth_path;
-            }
-        }
+    fn error_page(&self, status: Status) -> Response {
+        let html_page = self
+            .html_template(format!("<h1>{} {}</h1>", status.get_code(), status))
+            .into_bytes();
 
-        "/".to_string()
+        Response::builder()
+            .status(status)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .header("Content-Length", &html_page.len().to_string())
+            .body(html_page)
+            .into()
     }
 
     fn get_filenames(&self, path: &Path) -> io::Result<Vec<String>> {
         let links = fs::read_dir(path)?
-            .filter_map(|entry| match entry {
-                Ok(entry) => Some(entry),
-                _ => None,
-            })
-            .map(|entry| {
-                entry
-                    .path()
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_string()
-            })
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
             .map(|filename| format!("/{}", filename))
             .collect::<Vec<String>>();
 
@@ -90,26 +189,135 @@ impl FileServer {
     }
 }
 
+impl FileServer {
+    fn serve_file(&self, req: &Request, path: &Path, file: fs::File) -> io::Result<Response> {
+        let metadata = file.metadata()?;
+        let size = metadata.len();
+        let etag = FileServer::etag(&metadata);
+        let last_modified = FileServer::last_modified(&metadata)?;
+
+        let builder = Response::builder()
+            .header("Accept-Ranges", "bytes")
+            .header("ETag", &etag)
+            .header("Last-Modified", &format_http_date(last_modified));
+
+        match conditional::evaluate(req, &etag, last_modified) {
+            Precondition::Proceed => {}
+            Precondition::NotModified => return Ok(builder.status(Status::NotModified).into()),
+            Precondition::Failed => {
+                return Ok(builder
+                    .status(Status::PreconditionFailed)
+                    .header("Content-Length", "0")
+                    .into())
+            }
+        }
+
+        let ranges = match (&req.method, req.header.combined("Range")) {
+            (Method::GET, Some(range)) if self.if_range_matches(req, &etag, last_modified) => {
+                parse_range(&range, size)
+            }
+            _ => None,
+        };
+
+        let res = match ranges {
+            None => builder
+                .file_with_content_type_and_length(path, file)?
+                .into(),
+            Some(Ranges::Unsatisfiable) => builder
+                .status(Status::RequestedRangeNotSatisfiable)
+                .header("Content-Range", &format!("bytes */{}", size))
+                .header("Content-Length", "0")
+                .into(),
+            Some(Ranges::Satisfiable(ranges)) if ranges.len() == 1 => builder
+                .status(Status::PartialContent)
+                .header("Content-Range", &ranges[0].content_range(size))
+                .file_range_with_content_type_and_length(
+                    path,
+                    file,
+                    ranges[0].start,
+                    ranges[0].length(),
+                )
+                .into(),
+            Some(Ranges::Satisfiable(ranges)) => {
+                let multipart =
+                    MultipartRanges::new(file, &ranges, size, response::content_type(path));
+
+                builder
+                    .status(Status::PartialContent)
+                    .header("Content-Type", &multipart.content_type)
+                    .header("Content-Length", &multipart.content_length.to_string())
+                    .stream(multipart.reader)
+                    .into()
+            }
+        };
+
+        Ok(res)
+    }
+
+    /// A Range request only applies if the client's copy is still current
+    /// (RFC 7233, 3.2). Weak entity tags never match.
+    fn if_range_matches(&self, req: &Request, etag: &str, last_modified: SystemTime) -> bool {
+        let validator = match req.header.combined("If-Range") {
+            Some(validator) => validator,
+            None => return true,
+        };
+
+        if validator.starts_with('"') || validator.starts_with("W/") {
+            conditional::strong_eq(&validator, etag)
+        } else {
+            parse_http_date(&validator) == Some(last_modified)
+        }
+    }
+
+    /// A strong entity tag built from the file's inode, size and mtime. The
+    /// mtime may be before 1970, so its seconds are kept signed.
+    fn etag(metadata: &fs::Metadata) -> String {
+        format!(
+            "\"{:x}-{:x}-{:x}.{:x}\"",
+            metadata.ino(),
+            metadata.size(),
+            metadata.mtime(),
+            metadata.mtime_nsec()
+        )
+    }
+
+    /// Modification time truncated to the one second precision of HTTP dates.
+    fn last_modified(metadata: &fs::Metadata) -> io::Result<SystemTime> {
+        let modified = metadata.modified()?;
+        let secs = modified
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Ok(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+}
+
 impl Handler for FileServer {
-    fn serve_http(&self, req: Request) -> io::Result<Response> {
-        let req_url = req.url.path.to_string();
-        let file_path = format!("{}{}", self.path, req.url.path);
-        let path = Path::new(&file_path);
+    fn serve_http(&self, req: Request) -> Result<Response, Error> {
+        let (path, segments) = match self.resolve(&req.url.path) {
+            Ok(resolved) => resolved,
+            Err(status) => return Ok(self.error_page(status)),
+        };
 
         if path.is_file() {
-            if let Ok(file) = fs::read(path) {
-                let res = Response::builder()
-                    .body_with_content_type_and_length(path, file)
-                    .into();
-
-                return Ok(res);
+            if let Ok(file) = fs::File::open(&path) {
+                return Ok(self.serve_file(&req, &path, file)?);
             }
         }
 
         if path.is_dir() {
-            let links = self.get_filenames(path)?;
-            let parent_path = self.get_parent_path(path.parent().unwrap());
-            let html_body = self.build_body(req_url, parent_path, links);
+            let segments = segments
+                .iter()
+                .map(|segment| String::from_utf8_lossy(segment).to_string())
+                .collect::<Vec<String>>();
+            let req_url = format!("/{}", segments.join("/"));
+            let parent_path = format!(
+                "/{}",
+                segments[..segments.len().saturating_sub(1)].join("/")
+            );
+
+            let links = self.get_filenames(&path)?;
+            let html_body = self.build_body(&req.base_path, req_url, parent_path, links);
             let html_page = self.html_template(html_body).into_bytes();
 
             let res = Response::builder()
@@ -121,17 +329,168 @@ impl Handler for FileServer {
             return Ok(res);
         }
 
-        let html_not_found_page = self
-            .html_template("<h1>404 Content Not Found</h1>".to_string())
-            .into_bytes();
+        Ok(self.error_page(Status::NotFound))
+    }
+}
 
-        let res = Response::builder()
-            .status(Status::NotFound)
-            .header("Content-Type", "text/html; charset=utf-8")
-            .header("Content-Length", &html_not_found_page.len().to_string())
-            .body(html_not_found_page)
-            .into();
+#[cfg(test)]
+mod test_resolve {
+    use super::*;
+    use std::{env, os::unix::fs::symlink};
 
-        Ok(res)
+    /// Builds `<tmp>/<name>/{root,outside}` and returns a server for `root`.
+    fn fixture(name: &str) -> (FileServer, PathBuf) {
+        let base = env::temp_dir().join(format!("file_server_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+
+        let root = base.join("root");
+        let outside = base.join("outside");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(root.join("file.txt"), "inside").unwrap();
+        fs::write(root.join("sub").join("inner.txt"), "inner").unwrap();
+        fs::write(outside.join("secret.txt"), "secret").unwrap();
+        symlink(root.join("file.txt"), root.join("link_inside")).unwrap();
+        symlink(outside.join("secret.txt"), root.join("link_outside")).unwrap();
+        symlink(&outside, root.join("dir_outside")).unwrap();
+
+        let root_str: &'static str = Box::leak(root.to_str().unwrap().to_string().into_boxed_str());
+        let root = fs::canonicalize(&root).unwrap();
+        (FileServer::new(root_str), root)
+    }
+
+    fn status(result: Result<(PathBuf, Vec<Vec<u8>>), Status>) -> Option<u16> {
+        result.err().map(|status| status.get_code())
+    }
+
+    fn resolved(server: &FileServer, url: &str) -> PathBuf {
+        match server.resolve(url) {
+            Ok((path, _)) => path,
+            Err(status) => panic!("{} resolved to {}", url, status),
+        }
+    }
+
+    #[test]
+    fn test_resolve_should_serve_paths_inside_root() {
+        let (server, root) = fixture("inside");
+        let vals = [
+            ("/file.txt", root.join("file.txt")),
+            ("/sub/inner.txt", root.join("sub").join("inner.txt")),
+            ("/sub/../file.txt", root.join("file.txt")),
+            ("/./sub/./inner.txt", root.join("sub").join("inner.txt")),
+            ("//sub//inner.txt", root.join("sub").join("inner.txt")),
+            ("/%66ile.txt", root.join("file.txt")),
+            ("/", root.clone()),
+        ];
+
+        for (url, expected) in vals.iter() {
+            assert_eq!(*expected, resolved(&server, url));
+        }
+    }
+
+    #[test]
+    fn test_resolve_should_refuse_traversal_outside_root() {
+        let (server, _) = fixture("traversal");
+        let vals = [
+            "/../../etc/passwd",
+            "/..",
+            "/sub/../../outside/secret.txt",
+            "/%2e%2e/%2e%2e/etc/passwd",
+            "/%2E%2E/outside/secret.txt",
+            "/sub/%2e%2e/%2e%2e/outside/secret.txt",
+        ];
+
+        for url in vals.iter() {
+            assert_eq!(Some(403), status(server.resolve(url)), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_resolve_should_reject_malformed_paths() {
+        let (server, _) = fixture("malformed");
+        let vals = [
+            "/..%2f..%2fetc/passwd",
+            "/sub%2Finner.txt",
+            "/file.txt%00.html",
+            "/%zz",
+            "/%2",
+        ];
+
+        for url in vals.iter() {
+            assert_eq!(Some(400), status(server.resolve(url)), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_resolve_should_apply_symlink_policy() {
+        let (server, root) = fixture("symlinks");
+
+        assert_eq!(root.join("file.txt"), resolved(&server, "/link_inside"));
+        assert_eq!(Some(403), status(server.resolve("/link_outside")));
+        assert_eq!(Some(403), status(server.resolve("/dir_outside/secret.txt")));
+
+        let server = server.symlinks(SymlinkPolicy::Deny);
+        assert_eq!(Some(403), status(server.resolve("/link_inside")));
+        assert_eq!(Some(403), status(server.resolve("/link_outside")));
+        assert_eq!(Some(404), status(server.resolve("/missing.txt")));
+
+        let server = server.symlinks(SymlinkPolicy::Follow);
+        let path = resolved(&server, "/link_outside");
+        assert_eq!("secret", fs::read_to_string(path).unwrap());
+    }
+
+    #[test]
+    fn test_serve_http_should_not_leak_files_outside_root() {
+        let (server, _) = fixture("serve");
+        let req = Request::new(
+            Method::GET,
+            crate::http::url::URL::new("/../outside/secret.txt"),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+
+        let res = server.serve_http(req).unwrap();
+        assert_eq!(403, res.status.get_code());
+    }
+}
+
+#[cfg(test)]
+mod test_serve_file {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_serve_file_should_handle_mtime_before_1970() {
+        let root = env::temp_dir().join(format!("file_server_mtime_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let path = root.join("old.txt");
+        fs::write(&path, "old").unwrap();
+
+        let mtime = UNIX_EPOCH - Duration::new(86_400, 500);
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(mtime).unwrap();
+
+        let metadata = fs::metadata(&path).unwrap();
+        assert!(metadata.mtime() < 0);
+        let etag = FileServer::etag(&metadata);
+        assert!(etag.starts_with('"') && etag.ends_with('"'), "{}", etag);
+
+        let root: &'static str = Box::leak(root.to_str().unwrap().to_string().into_boxed_str());
+        let req = Request::new(
+            Method::GET,
+            crate::http::url::URL::new("/old.txt"),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+
+        let res = FileServer::new(root).serve_http(req).unwrap();
+        assert_eq!(200, res.status.get_code());
+        assert_eq!(Some(etag), res.header.get("ETag"));
+        assert_eq!(
+            Some(String::from("Thu, 01 Jan 1970 00:00:00 GMT")),
+            res.header.get("Last-Modified")
+        );
     }
 }
@@ -2,11 +2,13 @@ use super::{method::Method, url::URL, version::Version};
 use std::{
     collections::HashMap,
     default::Default,
+    fmt,
     io::{Error, ErrorKind},
     str::FromStr,
-    string::ToString,
 };
 
+/// Header fields keyed case-insensitively, as RFC 7230 requires. The casing
+/// of the first occurrence of a key is kept when writing it out.
 #[derive(Debug)]
 pub struct Header {
     headers: HashMap<String, Vec<String>>,
@@ -26,25 +28,26 @@ impl Header {
         let key = key
             .trim()
             .chars()
-            .filter(|c| c.is_alphanumeric() || *c == '-')
+            .filter(|c| c.is_ascii() && is_tchar(*c as u8))
             .collect::<String>();
 
         if key.is_empty() || key.len() != original_key.len() || val.is_empty() {
             return;
         }
 
-        if !self.headers.contains_key(&key) {
-            self.headers.insert(key.to_string(), vec![]);
+        let lowercase_key = key.to_ascii_lowercase();
+        if !self.headers.contains_key(&lowercase_key) {
+            self.headers.insert(lowercase_key.clone(), vec![]);
             self.ordered_keys.push(key.to_string());
         }
 
-        let values = self.headers.get_mut(&key).unwrap();
+        let values = self.headers.get_mut(&lowercase_key).unwrap();
         values.push(val.to_string());
     }
 
     pub fn del(&mut self, key: &str) {
-        if self.headers.contains_key(key) {
-            self.headers.remove(key);
+        if self.headers.remove(&key.to_ascii_lowercase()).is_some() {
+            self.ordered_keys.retain(|k| !k.eq_ignore_ascii_case(key));
         }
     }
 
@@ -53,14 +56,41 @@ impl Header {
     }
 
     pub fn values(&self, key: &str) -> Option<Vec<String>> {
-        if self.headers.contains_key(key) {
-            Some(self.headers.get(key).unwrap().clone())
-        } else {
-            None
-        }
+        self.headers.get(&key.to_ascii_lowercase()).cloned()
+    }
+
+    /// The elements of a comma separated list field like `Connection`,
+    /// across all of its field lines, with empty elements left out.
+    pub fn list(&self, key: &str) -> Option<Vec<String>> {
+        let values = self.values(key)?;
+        let elements = values
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(|element| element.trim_matches(|c| c == ' ' || c == '\t'))
+            .filter(|element| !element.is_empty())
+            .map(String::from)
+            .collect();
+
+        Some(elements)
+    }
+
+    /// All values of `key` joined back into one comma separated field value.
+    /// Needed for fields like dates that contain commas themselves.
+    pub fn combined(&self, key: &str) -> Option<String> {
+        self.values(key).map(|values| values.join(", "))
     }
 }
 
+/// Whether `s` is a token (RFC 7230, 3.2.6), the syntax of methods and
+/// field names.
+pub(super) fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(is_tchar)
+}
+
+fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
 impl Header {
     pub fn from_lines(lines: Vec<&str>) -> Result<Header, Error> {
         let mut header = Header::new();
@@ -133,21 +163,17 @@ impl FromStr for Header {
             return err;
         }
 
-        if let Err(e) = Method::from_str(first_line[0]) {
-            return Err(e);
-        } else if let Err(e) = URL::from_str(first_line[1]) {
-            return Err(e);
-        } else if let Err(e) = Version::from_str(first_line[2]) {
-            return Err(e);
-        }
+        Method::from_str(first_line[0])?;
+        URL::from_str(first_line[1])?;
+        Version::from_str(first_line[2])?;
 
-        let lines = lines.iter().skip(1).map(|s| *s).collect::<Vec<&str>>();
+        let lines = lines.iter().skip(1).copied().collect::<Vec<&str>>();
         Header::from_lines(lines)
     }
 }
 
-impl ToString for Header {
-    fn to_string(&self) -> String {
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let build_key_values_line = |key: &str| -> String {
             let values = self.values(key).unwrap();
             let values = values
@@ -171,7 +197,7 @@ impl ToString for Header {
             .map(|key| build_key_values_line(key))
             .collect::<Vec<String>>();
 
-        format!("{}\r\n", lines.join(""))
+        write!(f, "{}\r\n", lines.join(""))
     }
 }
 
@@ -188,7 +214,7 @@ mod test_method_add_and_get {
     #[test]
     fn test_add_should_only_add_alphanumeric_keys() {
         let mut header = Header::new();
-        let key_val_expected = vec![
+        let key_val_expected = [
             ("", "", None),
             (" ", " ", None),
             ("\n\t\r\n\t", "\n\t\r\n\t", None),
@@ -228,6 +254,41 @@ mod test_method_add_and_get {
         vals.iter().for_each(|val| header.add(key, val));
         assert_eq!(expected, header.get(key).unwrap());
     }
+
+    #[test]
+    fn test_get_should_ignore_key_case() {
+        let mut header = Header::new();
+        header.add("Content-Length", "5");
+        header.add("content-length", "6");
+
+        assert_eq!(
+            Some(vec![String::from("5"), String::from("6")]),
+            header.values("CONTENT-LENGTH")
+        );
+        assert_eq!("Content-Length: 5, 6\r\n\r\n", header.to_string());
+    }
+}
+
+#[cfg(test)]
+mod test_method_list {
+    use super::*;
+
+    #[test]
+    fn test_list_should_split_elements_of_all_field_lines() {
+        let mut header = Header::new();
+        header.add("Connection", "keep-alive, ,Upgrade");
+        header.add("Connection", "close");
+
+        assert_eq!(
+            Some(vec![
+                String::from("keep-alive"),
+                String::from("Upgrade"),
+                String::from("close")
+            ]),
+            header.list("connection")
+        );
+        assert_eq!(None, header.list("Upgrade"));
+    }
 }
 
 #[cfg(test)]
@@ -320,7 +381,7 @@ mod test_from_str {
 
     #[test]
     fn test_from_str_should_return_io_error_on_invalid_input() {
-        let vals = vec![
+        let vals = [
             "",
             "     ",
             "1232392asdljas",
@@ -372,8 +433,8 @@ mod test_from_str {
 
     #[test]
     fn test_from_str_should_return_io_error_on_unsupported_http_version() {
-        let vals = vec![
-            "GET / HTTP/1.0\r\n\r\n",
+        let vals = [
+            "GET / HTTP/0.9\r\n\r\n",
             "POST /valid HTTP/2\r\n\r\n",
             "PUT /valid HTTP/3\r\n\r\n",
         ];
@@ -405,7 +466,7 @@ mod test_from_str {
                 User-Agent: Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:81.0) Gecko/20100101 Firefox/81.0\r\n\r\n"
             ).unwrap();
 
-        let expected = vec![
+        let expected = [
                 ("Host", vec!["docs.apigee.com"]),
                 ("User-Agent", vec!["Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:81.0) Gecko/20100101 Firefox/81.0"]),
             ];
@@ -425,7 +486,7 @@ mod test_from_str {
                 Accept: text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,*/*;q=0.8\r\n\r\n"
             ).unwrap();
 
-        let expected = vec![
+        let expected = [
                 ("Host", Some(vec![String::from("docs.apigee.com")])),
                 ("User-Agent", Some(vec![String::from("Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:81.0) Gecko/20100101 Firefox/81.0")])),
                 ("Accept", Some(vec![
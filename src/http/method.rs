@@ -1,11 +1,12 @@
 use std::{
-    default::Default,
+    fmt,
     io::{Error, ErrorKind},
     str::FromStr,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum Method {
+    #[default]
     GET,
     HEAD,
     POST,
@@ -17,9 +18,25 @@ pub enum Method {
     PATCH,
 }
 
-impl Default for Method {
-    fn default() -> Self {
-        Method::GET
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::GET => "GET",
+            Method::HEAD => "HEAD",
+            Method::POST => "POST",
+            Method::PUT => "PUT",
+            Method::DELETE => "DELETE",
+            Method::CONNECT => "CONNECT",
+            Method::OPTIONS => "OPTIONS",
+            Method::TRACE => "TRACE",
+            Method::PATCH => "PATCH",
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
     }
 }
 
@@ -0,0 +1,139 @@
+use super::{request::Request, response::Response, server::Handler};
+use crate::error::Error;
+use std::time;
+
+/// Code running around a `Handler`. It can look at or change the request
+/// before passing it on with `next.run(req)`, change the response on the way
+/// back, or answer on its own without calling `next` at all.
+pub trait Middleware: Clone + Send + Sync + 'static {
+    fn handle(&self, req: Request, next: Next) -> Result<Response, Error>;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(Request, Next) -> Result<Response, Error> + Clone + Send + Sync + 'static,
+{
+    fn handle(&self, req: Request, next: Next) -> Result<Response, Error> {
+        self(req, next)
+    }
+}
+
+/// The rest of the chain below a middleware.
+pub struct Next<'a> {
+    inner: &'a dyn Fn(Request) -> Result<Response, Error>,
+}
+
+impl Next<'_> {
+    pub fn run(self, req: Request) -> Result<Response, Error> {
+        (self.inner)(req)
+    }
+}
+
+/// A `Handler` wrapped in a `Middleware`, see `HandlerExt::with`.
+#[derive(Clone)]
+pub struct Wrapped<M, H> {
+    middleware: M,
+    inner: H,
+}
+
+impl<M: Middleware, H: Handler> Handler for Wrapped<M, H> {
+    fn serve_http(&self, req: Request) -> Result<Response, Error> {
+        let next = Next {
+            inner: &|req| self.inner.serve_http(req),
+        };
+        self.middleware.handle(req, next)
+    }
+}
+
+pub trait HandlerExt: Handler + Sized {
+    /// Wraps the handler in `middleware`. Layers added later run first:
+    /// `handler.with(a).with(b)` sees requests pass through `b`, then `a`.
+    fn with<M: Middleware>(self, middleware: M) -> Wrapped<M, Self> {
+        Wrapped {
+            middleware,
+            inner: self,
+        }
+    }
+}
+
+impl<H: Handler> HandlerExt for H {}
+
+/// Logs the method, path, status and duration of every request.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Logger;
+
+impl Middleware for Logger {
+    fn handle(&self, req: Request, next: Next) -> Result<Response, Error> {
+        let now = time::Instant::now();
+        let (method, path) = (req.method, req.url.path.clone());
+
+        let res = next.run(req)?;
+        info!(
+            "{} {} -> {} in {}ms",
+            method,
+            path,
+            res.status.get_code(),
+            now.elapsed().as_millis()
+        );
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod test_middleware {
+    use super::*;
+    use crate::http::status::Status;
+    use std::str::FromStr;
+
+    fn handler(req: Request) -> Result<Response, Error> {
+        let trace = req.header.combined("X-Trace").unwrap_or_default();
+        Ok(Response::builder().body(trace.into_bytes()).into())
+    }
+
+    fn trace(name: &'static str) -> impl Middleware {
+        move |mut req: Request, next: Next| {
+            let trace = req.header.combined("X-Trace").unwrap_or_default();
+            req.header.del("X-Trace");
+            req.header.add("X-Trace", &format!("{}{}", trace, name));
+
+            let mut res = next.run(req)?;
+            res.header.add("X-Trace", name);
+            Ok(res)
+        }
+    }
+
+    fn serve(handler: &impl Handler, request_line: &str) -> Response {
+        let req = Request::from_str(&format!("{}\r\n\r\n", request_line)).unwrap();
+        handler.serve_http(req).unwrap()
+    }
+
+    #[test]
+    fn test_middleware_should_run_outermost_layer_first() {
+        let stack = handler.with(trace("a")).with(trace("b")).with(Logger);
+        let res = serve(&stack, "GET / HTTP/1.1");
+
+        assert_eq!(b"ba".to_vec(), res.body.get());
+        assert_eq!(
+            Some(vec![String::from("a"), String::from("b")]),
+            res.header.values("X-Trace")
+        );
+    }
+
+    #[test]
+    fn test_middleware_should_short_circuit() {
+        let auth = |req: Request, next: Next| match req.header.get("Authorization") {
+            Some(_) => next.run(req),
+            None => Ok(Response::builder().status(Status::Unauthorized).into()),
+        };
+        let stack = handler.with(trace("inner")).with(auth);
+
+        let res = serve(&stack, "GET / HTTP/1.1");
+        assert_eq!(401, res.status.get_code());
+        assert_eq!(None, res.header.get("X-Trace"));
+
+        let res = serve(&stack, "GET / HTTP/1.1\r\nAuthorization: yes");
+        assert_eq!(200, res.status.get_code());
+        assert_eq!(b"inner".to_vec(), res.body.get());
+    }
+}
@@ -0,0 +1,791 @@
+use super::{
+    body::Body,
+    chunked::ChunkedDecoder,
+    header::{self, Header},
+    request::{InvalidHttpRequestError, Request},
+    version::Version,
+};
+use crate::error::Error;
+use std::{fmt, str};
+
+const HEAD_TERMINATOR: &[u8] = b"\r\n\r\n";
+
+/// Upper bounds on what a client may send, checked while parsing so an
+/// oversized request is turned down before it is buffered.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Longest request line, answered with `414 URI Too Long`.
+    pub max_request_line: usize,
+    /// Most header fields in a request, answered with
+    /// `431 Request Header Fields Too Large`.
+    pub max_headers: usize,
+    /// Longest request head: the request line and all header fields. Also
+    /// answered with a 431.
+    pub max_head_size: usize,
+    /// Largest body, whether framed by `Content-Length` or chunked. Answered
+    /// with `413 Payload Too Large` as soon as it is known to be too large.
+    pub max_body_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_request_line: 8 * 1024,
+            max_headers: 100,
+            max_head_size: 64 * 1024,
+            max_body_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Which of the `Limits` a request broke.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    /// `max_request_line`.
+    RequestLine,
+    /// `max_headers` or `max_head_size`.
+    Headers,
+    /// `max_body_size`.
+    Body,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::RequestLine => write!(f, "Request line too long!"),
+            Limit::Headers => write!(f, "Request header too large!"),
+            Limit::Body => write!(f, "Request body too large!"),
+        }
+    }
+}
+
+/// Result of feeding bytes into a `RequestParser`.
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum ParseStatus {
+    /// More bytes are needed before a request can be produced.
+    Incomplete,
+    /// A full request was parsed. Bytes past its end stay buffered.
+    Complete(Request),
+}
+
+#[derive(Default)]
+enum State {
+    #[default]
+    Head,
+    Body {
+        req: Request,
+        remaining: usize,
+    },
+    Chunked {
+        req: Request,
+        decoder: ChunkedDecoder,
+        /// Where the body starts, for pointing at errors in it.
+        head_size: usize,
+    },
+}
+
+enum Framing {
+    Length(usize),
+    Chunked,
+}
+
+/// Incremental HTTP/1.1 request parser.
+///
+/// Bytes can arrive in chunks of any size; the parser buffers whatever it
+/// cannot use yet, so pipelined requests are returned one `feed` at a time.
+#[derive(Default)]
+pub struct RequestParser {
+    buffer: Vec<u8>,
+    scanned: usize,
+    /// Bytes of the current request received so far.
+    received: usize,
+    state: State,
+    limits: Limits,
+}
+
+impl RequestParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(limits: Limits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    /// Number of bytes received but not yet consumed by a request.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// True when no part of a request has been received yet.
+    pub fn is_idle(&self) -> bool {
+        self.buffer.is_empty() && matches!(self.state, State::Head)
+    }
+
+    /// True once the head of a request was parsed and its body is still
+    /// coming in.
+    pub fn is_reading_body(&self) -> bool {
+        !matches!(self.state, State::Head)
+    }
+
+    /// The error for a connection that closed before the current request
+    /// was complete.
+    pub fn incomplete(&self) -> Error {
+        invalid(InvalidHttpRequestError::Incomplete, self.received)
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<ParseStatus, Error> {
+        self.buffer.extend_from_slice(chunk);
+        self.received += chunk.len();
+
+        loop {
+            match std::mem::take(&mut self.state) {
+                State::Head => {
+                    let head_end = self.find_head_end();
+                    self.check_request_line()?;
+
+                    let head_end = match head_end {
+                        Some(head_end) if head_end > self.limits.max_head_size => {
+                            return Err(Error::LimitExceeded(Limit::Headers));
+                        }
+                        Some(head_end) => head_end,
+                        None if self.buffer.len() > self.limits.max_head_size => {
+                            return Err(Error::LimitExceeded(Limit::Headers));
+                        }
+                        None => return Ok(ParseStatus::Incomplete),
+                    };
+
+                    let head = self.buffer.drain(..head_end).collect::<Vec<u8>>();
+                    self.scanned = 0;
+
+                    let (req, framing) = RequestParser::parse_head(&head, &self.limits)?;
+                    self.state = match framing {
+                        Framing::Length(0) => return Ok(self.complete(req)),
+                        Framing::Length(remaining) if remaining > self.limits.max_body_size => {
+                            return Err(Error::LimitExceeded(Limit::Body));
+                        }
+                        Framing::Length(remaining) => State::Body { req, remaining },
+                        Framing::Chunked => State::Chunked {
+                            req,
+                            decoder: ChunkedDecoder::new(self.limits.max_body_size),
+                            head_size: head_end,
+                        },
+                    };
+                }
+                State::Body { mut req, remaining } => {
+                    let take = remaining.min(self.buffer.len());
+                    req.body.extend(&self.buffer[..take]);
+                    self.buffer.drain(..take);
+
+                    if take < remaining {
+                        self.state = State::Body {
+                            req,
+                            remaining: remaining - take,
+                        };
+                        return Ok(ParseStatus::Incomplete);
+                    }
+
+                    return Ok(self.complete(req));
+                }
+                State::Chunked {
+                    mut req,
+                    mut decoder,
+                    head_size,
+                } => match decoder.decode(&mut self.buffer, &mut req.body) {
+                    Ok(Some(trailer)) => {
+                        req.trailer = trailer;
+                        return Ok(self.complete(req));
+                    }
+                    Ok(None) => {
+                        self.state = State::Chunked {
+                            req,
+                            decoder,
+                            head_size,
+                        };
+                        return Ok(ParseStatus::Incomplete);
+                    }
+                    Err(Error::Parse { reason, position }) => {
+                        return Err(invalid(reason, head_size + position));
+                    }
+                    Err(e) => return Err(e),
+                },
+            }
+        }
+    }
+
+    /// Whatever is left in the buffer belongs to the next request.
+    fn complete(&mut self, req: Request) -> ParseStatus {
+        self.received = self.buffer.len();
+        ParseStatus::Complete(req)
+    }
+
+    /// Returns the index just past the blank line ending the request head.
+    fn find_head_end(&mut self) -> Option<usize> {
+        // Clients may send stray CRLFs between pipelined requests.
+        while self.buffer.starts_with(b"\r\n") {
+            self.buffer.drain(..2);
+        }
+
+        let start = self.scanned.saturating_sub(HEAD_TERMINATOR.len() - 1);
+        match self.buffer[start..]
+            .windows(HEAD_TERMINATOR.len())
+            .position(|window| window == HEAD_TERMINATOR)
+        {
+            Some(pos) => Some(start + pos + HEAD_TERMINATOR.len()),
+            None => {
+                self.scanned = self.buffer.len();
+                None
+            }
+        }
+    }
+
+    /// Fails once the request line is known to be longer than allowed,
+    /// whether or not it is complete yet.
+    fn check_request_line(&self) -> Result<(), Error> {
+        let max_request_line = self.limits.max_request_line;
+        let scan = &self.buffer[..self.buffer.len().min(max_request_line + 2)];
+
+        match scan.windows(2).position(|window| window == b"\r\n") {
+            None if self.buffer.len() > max_request_line => {
+                Err(Error::LimitExceeded(Limit::RequestLine))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Parses the request head and works out how the body is framed. Errors
+    /// point at the part of the request line or the header field that is
+    /// wrong.
+    fn parse_head(head: &[u8], limits: &Limits) -> Result<(Request, Framing), Error> {
+        let head = str::from_utf8(head)
+            .map_err(|e| invalid(InvalidHttpRequestError::Malformed, e.valid_up_to()))?;
+        let header_lines = head
+            .trim_end_matches("\r\n")
+            .split("\r\n")
+            .collect::<Vec<&str>>();
+        let line_offsets = header_lines
+            .iter()
+            .scan(0, |offset, line| {
+                let line_offset = *offset;
+                *offset += line.len() + 2;
+                Some(line_offset)
+            })
+            .collect::<Vec<usize>>();
+
+        if header_lines.len() > limits.max_headers + 1 {
+            return Err(Error::LimitExceeded(Limit::Headers));
+        }
+
+        let request_line = header_lines[0].split(' ').collect::<Vec<&str>>();
+        if request_line.len() != 3 {
+            return Err(invalid(InvalidHttpRequestError::InvalidRequestLine, 0));
+        }
+        let url_offset = request_line[0].len() + 1;
+        let version_offset = url_offset + request_line[1].len() + 1;
+
+        let method =
+            Request::create_method(request_line[0]).map_err(|reason| invalid(reason, 0))?;
+        let url =
+            Request::create_url(request_line[1]).map_err(|reason| invalid(reason, url_offset))?;
+        let http_version = Request::create_http_version(request_line[2])
+            .map_err(|reason| invalid(reason, version_offset))?;
+        let header = parse_fields(&header_lines[1..]).map_err(|line| {
+            invalid(
+                InvalidHttpRequestError::InvalidHeader,
+                line_offsets[line + 1],
+            )
+        })?;
+
+        let req = Request::new(method, url, http_version, header, Body::default());
+        let framing = RequestParser::framing(&req).map_err(|reason| {
+            let field = match reason {
+                InvalidHttpRequestError::InvalidContentLength => "content-length:",
+                _ => "transfer-encoding:",
+            };
+            let line = header_lines
+                .iter()
+                .position(|line| line.to_ascii_lowercase().starts_with(field))
+                .unwrap_or(0);
+            invalid(reason, line_offsets[line])
+        })?;
+
+        Ok((req, framing))
+    }
+
+    /// Works out how the body is delimited. A request carrying both
+    /// `Transfer-Encoding` and `Content-Length` is rejected outright, since
+    /// intermediaries may disagree on which one wins (request smuggling).
+    /// So is an HTTP/1.0 request with `Transfer-Encoding`: 1.0 has no such
+    /// field, so whatever sent it can't be trusted to have framed it right.
+    fn framing(req: &Request) -> Result<Framing, InvalidHttpRequestError> {
+        let encodings = match req.header.list("Transfer-Encoding") {
+            Some(encodings) => encodings,
+            None => return RequestParser::content_length(req).map(Framing::Length),
+        };
+
+        if req.http_version == Version::V1P0 {
+            return Err(InvalidHttpRequestError::TransferEncodingInHttp10);
+        }
+
+        if req.header.get("Content-Length").is_some() {
+            return Err(InvalidHttpRequestError::AmbiguousFraming);
+        }
+
+        match &encodings[..] {
+            [encoding] if encoding.eq_ignore_ascii_case("chunked") => Ok(Framing::Chunked),
+            _ => Err(InvalidHttpRequestError::UnsupportedTransferEncoding),
+        }
+    }
+
+    /// Repeated `Content-Length` fields and comma separated lists both make
+    /// the body length ambiguous: the former end up as several values, the
+    /// latter aren't all digits.
+    fn content_length(req: &Request) -> Result<usize, InvalidHttpRequestError> {
+        let lengths = match req.header.values("Content-Length") {
+            Some(lengths) => lengths,
+            None => return Ok(0),
+        };
+
+        if lengths.len() != 1 || !lengths[0].bytes().all(|b| b.is_ascii_digit()) {
+            return Err(InvalidHttpRequestError::InvalidContentLength);
+        }
+
+        // Only digits are left, so parsing can only fail on overflow, which
+        // is over any body size limit.
+        Ok(lengths[0].parse().unwrap_or(usize::MAX))
+    }
+}
+
+/// Parses header or trailer field lines into a `Header`, keeping each field
+/// value whole. On error returns the index of the offending line.
+pub(super) fn parse_fields(lines: &[&str]) -> Result<Header, usize> {
+    let mut header = Header::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        let (name, value) = parse_field(line).ok_or(i)?;
+        header.add(name, value);
+    }
+
+    Ok(header)
+}
+
+/// Splits `name: value` at the first colon. The name must be a token with
+/// no whitespace before the colon; optional whitespace around the value is
+/// dropped.
+fn parse_field(line: &str) -> Option<(&str, &str)> {
+    let (name, value) = line.split_once(':')?;
+    if !header::is_token(name) {
+        return None;
+    }
+
+    Some((name, value.trim_matches(|c| c == ' ' || c == '\t')))
+}
+
+fn invalid(reason: InvalidHttpRequestError, position: usize) -> Error {
+    Error::Parse { reason, position }
+}
+
+#[cfg(test)]
+mod test_feed {
+    use super::*;
+
+    fn complete(status: ParseStatus) -> Request {
+        match status {
+            ParseStatus::Complete(req) => req,
+            ParseStatus::Incomplete => panic!("Request should be complete!"),
+        }
+    }
+
+    #[test]
+    fn test_feed_should_parse_request_in_one_chunk() {
+        let mut parser = RequestParser::new();
+        let req = complete(
+            parser
+                .feed(b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap(),
+        );
+
+        assert_eq!("/index.html", req.url.path);
+        assert_eq!(Some(String::from("localhost")), req.header.get("Host"));
+        assert!(req.body.is_empty());
+    }
+
+    #[test]
+    fn test_feed_should_parse_field_lines_by_the_token_rules() {
+        let req = complete(
+            RequestParser::new()
+                .feed(
+                    b"GET / HTTP/1.1\r\nHost:example.com\r\nX-Name:  a b \t\r\n\
+                      Accept: text/html, */*\r\nX_Odd.Name!: 1\r\nX-Empty:\r\n\r\n",
+                )
+                .unwrap(),
+        );
+
+        assert_eq!(Some(String::from("example.com")), req.header.get("Host"));
+        assert_eq!(Some(String::from("a b")), req.header.get("X-Name"));
+        assert_eq!(
+            Some(vec![String::from("text/html, */*")]),
+            req.header.values("Accept")
+        );
+        assert_eq!(Some(String::from("1")), req.header.get("X_Odd.Name!"));
+        assert_eq!(None, req.header.get("X-Empty"));
+    }
+
+    #[test]
+    fn test_feed_should_parse_http_1_0_request() {
+        let vals = [
+            ("GET / HTTP/1.0\r\n\r\n", Version::V1P0, false),
+            (
+                "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+                Version::V1P0,
+                true,
+            ),
+            (
+                "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                Version::V1P1,
+                true,
+            ),
+            (
+                "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                Version::V1P1,
+                false,
+            ),
+        ];
+
+        for (raw, version, keep_alive) in vals.iter() {
+            let req = complete(RequestParser::new().feed(raw.as_bytes()).unwrap());
+            assert_eq!(*version, req.http_version, "{}", raw);
+            assert_eq!(*keep_alive, req.wants_keep_alive(), "{}", raw);
+        }
+    }
+
+    #[test]
+    fn test_feed_should_parse_request_split_byte_by_byte() {
+        let raw = b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello";
+        let mut parser = RequestParser::new();
+
+        for byte in &raw[..raw.len() - 1] {
+            match parser.feed(&[*byte]).unwrap() {
+                ParseStatus::Incomplete => {}
+                ParseStatus::Complete(_) => panic!("Request shouldn't be complete yet!"),
+            }
+        }
+
+        let req = complete(parser.feed(&raw[raw.len() - 1..]).unwrap());
+        assert_eq!(b"hello".to_vec(), req.body.get());
+    }
+
+    #[test]
+    fn test_feed_should_keep_binary_body_intact() {
+        let mut raw = b"PUT /blob HTTP/1.1\r\nContent-Length: 6\r\n\r\n".to_vec();
+        raw.extend_from_slice(&[0xff, 0x00, b'\r', b'\n', 0xfe, 0x80]);
+
+        let req = complete(RequestParser::new().feed(&raw).unwrap());
+        assert_eq!(vec![0xff, 0x00, b'\r', b'\n', 0xfe, 0x80], req.body.get());
+    }
+
+    #[test]
+    fn test_feed_should_read_exactly_content_length_bytes() {
+        let mut parser = RequestParser::new();
+        let req = complete(
+            parser
+                .feed(b"POST /json HTTP/1.1\r\ncontent-length: 8\r\n\r\n{\r\n\r\n\"\"}GET / HTTP/1.1\r\n")
+                .unwrap(),
+        );
+
+        assert_eq!(b"{\r\n\r\n\"\"}".to_vec(), req.body.get());
+        assert_eq!(b"GET / HTTP/1.1\r\n".len(), parser.buffered());
+        assert!(!parser.is_idle());
+    }
+
+    #[test]
+    fn test_feed_should_report_reading_body() {
+        let mut parser = RequestParser::new();
+        assert!(matches!(
+            parser.feed(b"POST / HTTP/1.1\r\n").unwrap(),
+            ParseStatus::Incomplete
+        ));
+        assert!(!parser.is_reading_body());
+
+        assert!(matches!(
+            parser.feed(b"Content-Length: 4\r\n\r\nab").unwrap(),
+            ParseStatus::Incomplete
+        ));
+        assert!(parser.is_reading_body());
+
+        complete(parser.feed(b"cd").unwrap());
+        assert!(!parser.is_reading_body());
+    }
+
+    #[test]
+    fn test_feed_should_reject_ambiguous_content_length() {
+        let vals = [
+            "POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\nhello",
+            "POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\nhello!",
+            "POST / HTTP/1.1\r\nContent-Length: 5, 6\r\n\r\nhello!",
+            "POST / HTTP/1.1\r\nContent-Length: +5\r\n\r\nhello",
+            "POST / HTTP/1.1\r\nContent-Length: -5\r\n\r\n",
+        ];
+
+        for val in vals.iter() {
+            assert!(RequestParser::new().feed(val.as_bytes()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_feed_should_decode_chunked_body() {
+        let mut parser = RequestParser::new();
+        let head = b"POST /stream HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+
+        assert!(matches!(
+            parser.feed(head).unwrap(),
+            ParseStatus::Incomplete
+        ));
+        assert!(matches!(
+            parser.feed(b"5\r\nhello\r\n").unwrap(),
+            ParseStatus::Incomplete
+        ));
+
+        let req = complete(parser.feed(b"0\r\nChecksum: 42\r\n\r\n").unwrap());
+        assert_eq!(b"hello".to_vec(), req.body.get());
+        assert_eq!(Some(String::from("42")), req.trailer.get("Checksum"));
+        assert_eq!(None, req.header.get("Checksum"));
+    }
+
+    #[test]
+    fn test_feed_should_reject_ambiguous_transfer_encoding() {
+        let vals = [
+            "POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n",
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n",
+            "POST / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n",
+            "POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n",
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked, chunked\r\n\r\n",
+            "POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n",
+        ];
+
+        for val in vals.iter() {
+            assert!(RequestParser::new().feed(val.as_bytes()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_feed_should_return_pipelined_requests_one_at_a_time() {
+        let mut parser = RequestParser::new();
+        let first = complete(
+            parser
+                .feed(b"GET /first HTTP/1.1\r\n\r\nGET /second HTTP/1.1\r\n\r\n")
+                .unwrap(),
+        );
+        let second = complete(parser.feed(&[]).unwrap());
+
+        assert_eq!("/first", first.url.path);
+        assert_eq!("/second", second.url.path);
+        assert_eq!(0, parser.buffered());
+    }
+
+    #[test]
+    fn test_feed_should_return_error_on_invalid_input() {
+        let vals = [
+            "GET\r\n\r\n",
+            "GET / HTTP/1.1 extra\r\n\r\n",
+            "UNKNOWN / HTTP/1.1\r\n\r\n",
+            "GET invalid/ HTTP/1.1\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost docs.apigee.com\r\n\r\n",
+            "POST / HTTP/1.1\r\nContent-Length: five\r\n\r\n",
+        ];
+
+        for val in vals.iter() {
+            assert!(RequestParser::new().feed(val.as_bytes()).is_err());
+        }
+        assert!(RequestParser::new()
+            .feed(b"GET /\xff HTTP/1.1\r\n\r\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test_feed_should_say_what_was_wrong() {
+        let vals = [
+            (
+                "GET\r\n\r\n",
+                InvalidHttpRequestError::InvalidRequestLine,
+                0,
+                400,
+            ),
+            (
+                "G(T / HTTP/1.1\r\n\r\n",
+                InvalidHttpRequestError::InvalidRequestLine,
+                0,
+                400,
+            ),
+            (
+                "UNKNOWN / HTTP/1.1\r\n\r\n",
+                InvalidHttpRequestError::UnknownMethod,
+                0,
+                501,
+            ),
+            (
+                "GET invalid/ HTTP/1.1\r\n\r\n",
+                InvalidHttpRequestError::InvalidUrl,
+                4,
+                400,
+            ),
+            (
+                "GET / HTTP/2.0\r\n\r\n",
+                InvalidHttpRequestError::UnsupportedVersion,
+                6,
+                505,
+            ),
+            (
+                "GET / HTTX/1.1\r\n\r\n",
+                InvalidHttpRequestError::InvalidRequestLine,
+                6,
+                400,
+            ),
+            (
+                "GET / HTTP/1.1\r\nHost\r\n\r\n",
+                InvalidHttpRequestError::InvalidHeader,
+                16,
+                400,
+            ),
+            (
+                "GET / HTTP/1.1\r\nHost: x\r\nBad Name: x\r\n\r\n",
+                InvalidHttpRequestError::InvalidHeader,
+                25,
+                400,
+            ),
+            (
+                "GET / HTTP/1.1\r\nHost : x\r\n\r\n",
+                InvalidHttpRequestError::InvalidHeader,
+                16,
+                400,
+            ),
+            (
+                "GET / HTTP/1.1\r\n Host: x\r\n\r\n",
+                InvalidHttpRequestError::InvalidHeader,
+                16,
+                400,
+            ),
+            (
+                "GET / HTTP/1.1\r\n: x\r\n\r\n",
+                InvalidHttpRequestError::InvalidHeader,
+                16,
+                400,
+            ),
+            (
+                "GET / HTTP/1.1\r\nHo(st: x\r\n\r\n",
+                InvalidHttpRequestError::InvalidHeader,
+                16,
+                400,
+            ),
+            (
+                "POST / HTTP/1.1\r\nContent-Length: five\r\n\r\n",
+                InvalidHttpRequestError::InvalidContentLength,
+                17,
+                400,
+            ),
+            (
+                "POST / HTTP/1.1\r\nContent-Length: 1\r\nTransfer-Encoding: chunked\r\n\r\n",
+                InvalidHttpRequestError::AmbiguousFraming,
+                36,
+                400,
+            ),
+            (
+                "POST / HTTP/1.0\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n",
+                InvalidHttpRequestError::TransferEncodingInHttp10,
+                26,
+                400,
+            ),
+            (
+                "POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n",
+                InvalidHttpRequestError::UnsupportedTransferEncoding,
+                17,
+                501,
+            ),
+            (
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\nzz\r\n",
+                InvalidHttpRequestError::InvalidChunk,
+                53,
+                400,
+            ),
+        ];
+
+        for (val, reason, position, code) in vals.iter() {
+            let err = RequestParser::new().feed(val.as_bytes()).unwrap_err();
+            assert_eq!(*code, err.status().get_code(), "{:?}", val);
+            match err {
+                Error::Parse {
+                    reason: actual_reason,
+                    position: actual_position,
+                } => assert_eq!((*reason, *position), (actual_reason, actual_position)),
+                e => panic!("{:?} should fail to parse, not with {}", val, e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_feed_should_enforce_limits() {
+        let limits = Limits {
+            max_request_line: 32,
+            max_headers: 2,
+            max_head_size: 64,
+            max_body_size: 4,
+        };
+        let limit_exceeded = |result: Result<ParseStatus, Error>| match result {
+            Err(Error::LimitExceeded(limit)) => limit,
+            other => panic!("Limit should be exceeded: {:?}", other),
+        };
+
+        let mut parser = RequestParser::with_limits(limits);
+        assert!(parser.feed(b"GET / HTTP/1.1\r\n").is_ok());
+        assert_eq!(Limit::Headers, limit_exceeded(parser.feed(&[b'a'; 64])));
+
+        let mut parser = RequestParser::with_limits(limits);
+        assert!(parser.feed(b"GET /aaaaaaaaaaaaaaaaaaaaaa").is_ok());
+        assert_eq!(
+            Limit::RequestLine,
+            limit_exceeded(parser.feed(b"aaaaaaaaaa"))
+        );
+
+        let vals = [
+            (
+                "GET /aaaaaaaaaaaaaaaaaaaaaaaaaaaa HTTP/1.1\r\n\r\n",
+                Limit::RequestLine,
+            ),
+            (
+                "GET / HTTP/1.1\r\nX-Long: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n\r\n",
+                Limit::Headers,
+            ),
+            (
+                "GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n",
+                Limit::Headers,
+            ),
+            (
+                "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n",
+                Limit::Body,
+            ),
+            (
+                "POST / HTTP/1.1\r\nContent-Length: 99999999999999999999999\r\n\r\n",
+                Limit::Body,
+            ),
+            (
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n",
+                Limit::Body,
+            ),
+        ];
+        for (val, expected) in vals.iter() {
+            let result = RequestParser::with_limits(limits).feed(val.as_bytes());
+            assert_eq!(*expected, limit_exceeded(result), "{:?}", val);
+        }
+
+        let req = complete(
+            RequestParser::with_limits(limits)
+                .feed(b"POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\nabcd")
+                .unwrap(),
+        );
+        assert_eq!(b"abcd".to_vec(), req.body.get());
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    collections::hash_map::RandomState,
+    fs::File,
+    hash::{BuildHasher, Hasher},
+    io::{self, Read},
+    os::unix::fs::FileExt,
+};
+
+/// Most ranges served from a single `Range` header. Anything beyond that is
+/// answered with the full representation instead.
+const MAX_RANGES: usize = 16;
+
+/// An inclusive range of byte positions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn length(&self) -> u64 {
+        self.end + 1 - self.start
+    }
+
+    pub fn content_range(&self, size: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, size)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Ranges {
+    Satisfiable(Vec<ByteRange>),
+    Unsatisfiable,
+}
+
+/// Parses a `Range` header value against a representation of `size` bytes
+/// (RFC 7233, 2.1). Returns `None` when the header should be ignored and the
+/// full representation served.
+pub fn parse_range(value: &str, size: u64) -> Option<Ranges> {
+    let value = value.trim();
+    match value.get(..6) {
+        Some(unit) if unit.eq_ignore_ascii_case("bytes=") => {}
+        _ => return None,
+    }
+
+    let mut ranges = vec![];
+    let mut specs = 0;
+    for spec in value[6..].split(',') {
+        let spec = spec.trim();
+        if spec.is_empty() {
+            continue;
+        }
+        specs += 1;
+
+        let (first, last) = match spec.find('-') {
+            Some(dash) => (&spec[..dash], &spec[dash + 1..]),
+            None => return None,
+        };
+
+        let range = if first.is_empty() {
+            let suffix = parse_position(last)?;
+            if suffix == 0 || size == 0 {
+                continue;
+            }
+            ByteRange {
+                start: size.saturating_sub(suffix),
+                end: size - 1,
+            }
+        } else {
+            let start = parse_position(first)?;
+            let end = match last {
+                "" => u64::MAX,
+                last => parse_position(last)?,
+            };
+            if end < start {
+                return None;
+            }
+            if start >= size {
+                continue;
+            }
+            ByteRange {
+                start,
+                end: end.min(size - 1),
+            }
+        };
+
+        ranges.push(range);
+    }
+
+    // A header without a single range is invalid, not unsatisfiable.
+    if specs == 0 {
+        return None;
+    }
+    if ranges.is_empty() {
+        return Some(Ranges::Unsatisfiable);
+    }
+
+    let ranges = coalesce(ranges);
+    if ranges.len() > MAX_RANGES {
+        return None;
+    }
+
+    Some(Ranges::Satisfiable(ranges))
+}
+
+/// A `multipart/byteranges` body (RFC 7233, 4.1) streamed from `file`.
+pub struct MultipartRanges {
+    pub content_type: String,
+    pub content_length: u64,
+    pub reader: MultipartReader,
+}
+
+impl MultipartRanges {
+    pub fn new(file: File, ranges: &[ByteRange], size: u64, part_content_type: &str) -> Self {
+        let boundary = format!("{:016x}", RandomState::new().build_hasher().finish());
+
+        let mut parts = vec![];
+        for (i, range) in ranges.iter().enumerate() {
+            let part_head = format!(
+                "{}--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                if i == 0 { "" } else { "\r\n" },
+                boundary,
+                part_content_type,
+                range.content_range(size)
+            );
+            parts.push(Part::Bytes(io::Cursor::new(part_head.into_bytes())));
+            parts.push(Part::Segment(*range));
+        }
+        let closing = format!("\r\n--{}--\r\n", boundary);
+        parts.push(Part::Bytes(io::Cursor::new(closing.into_bytes())));
+
+        let content_length = parts.iter().map(Part::length).sum();
+        parts.reverse();
+
+        Self {
+            content_type: format!("multipart/byteranges; boundary={}", boundary),
+            content_length,
+            reader: MultipartReader { file, parts },
+        }
+    }
+}
+
+enum Part {
+    Bytes(io::Cursor<Vec<u8>>),
+    Segment(ByteRange),
+}
+
+impl Part {
+    fn length(&self) -> u64 {
+        match self {
+            Part::Bytes(bytes) => bytes.get_ref().len() as u64,
+            Part::Segment(range) => range.length(),
+        }
+    }
+}
+
+/// Reads the parts in order. File segments use positioned reads, so no
+/// seeking is needed between them.
+pub struct MultipartReader {
+    file: File,
+    parts: Vec<Part>,
+}
+
+impl Read for MultipartReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(part) = self.parts.last_mut() {
+            let read_bytes = match part {
+                Part::Bytes(bytes) => bytes.read(buf)?,
+                Part::Segment(range) => {
+                    let want = buf.len().min(range.length() as usize);
+                    let read_bytes = self.file.read_at(&mut buf[..want], range.start)?;
+                    if read_bytes == 0 && want > 0 {
+                        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+                    }
+                    range.start += read_bytes as u64;
+                    read_bytes
+                }
+            };
+
+            if read_bytes > 0 || buf.is_empty() {
+                return Ok(read_bytes);
+            }
+            self.parts.pop();
+        }
+
+        Ok(0)
+    }
+}
+
+fn parse_position(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// Merges overlapping and adjacent ranges so no byte is sent twice.
+fn coalesce(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+    ranges.sort_by_key(|range| range.start);
+
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => {
+                last.end = last.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod test_parse_range {
+    use super::*;
+
+    fn range(start: u64, end: u64) -> ByteRange {
+        ByteRange { start, end }
+    }
+
+    #[test]
+    fn test_parse_range_should_handle_single_ranges() {
+        let vals = [
+            ("bytes=0-499", range(0, 499)),
+            ("bytes=500-999", range(500, 999)),
+            ("bytes=9500-", range(9500, 9999)),
+            ("bytes=-500", range(9500, 9999)),
+            ("bytes=0-0", range(0, 0)),
+            ("bytes=9000-20000", range(9000, 9999)),
+            ("bytes=-20000", range(0, 9999)),
+            ("BYTES= 10-19 ", range(10, 19)),
+        ];
+
+        for (val, expected) in vals.iter() {
+            assert_eq!(
+                Some(Ranges::Satisfiable(vec![*expected])),
+                parse_range(val, 10000)
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_range_should_handle_multiple_ranges() {
+        assert_eq!(
+            Some(Ranges::Satisfiable(vec![range(0, 0), range(9999, 9999)])),
+            parse_range("bytes=-1,0-0", 10000)
+        );
+        assert_eq!(
+            Some(Ranges::Satisfiable(vec![range(0, 199)])),
+            parse_range("bytes=0-99, 50-149, 150-199", 10000)
+        );
+    }
+
+    #[test]
+    fn test_parse_range_should_report_unsatisfiable_ranges() {
+        let vals = [
+            "bytes=10000-",
+            "bytes=20000-30000",
+            "bytes=-0",
+            "bytes=10000-, -0",
+        ];
+
+        for val in vals.iter() {
+            assert_eq!(Some(Ranges::Unsatisfiable), parse_range(val, 10000));
+        }
+        assert_eq!(Some(Ranges::Unsatisfiable), parse_range("bytes=-5", 0));
+    }
+
+    #[test]
+    fn test_parse_range_should_ignore_invalid_headers() {
+        let vals = [
+            "",
+            "items=0-5",
+            "bytes=5",
+            "bytes=5-1",
+            "bytes=a-b",
+            "bytes=--5",
+            "bytes=+1-5",
+            "bytes=",
+            "bytes= , ,",
+        ];
+
+        for val in vals.iter() {
+            assert_eq!(None, parse_range(val, 10000));
+        }
+
+        let too_many = (0..=MAX_RANGES)
+            .map(|i| format!("{}-{}", i * 10, i * 10))
+            .collect::<Vec<String>>()
+            .join(",");
+        assert_eq!(None, parse_range(&format!("bytes={}", too_many), 10000));
+    }
+}
@@ -1,14 +1,82 @@
-use super::{body::Body, header::Header, method::Method, url::URL, version::Version};
-use std::{default::Default, error::Error, fmt, str::FromStr};
+use super::{
+    body::Body,
+    header::{self, Header},
+    method::Method,
+    parser::{ParseStatus, RequestParser},
+    status::Status,
+    url::URL,
+    version::Version,
+};
+use crate::net::socket::PeerCredentials;
+use std::{collections::HashMap, default::Default, error::Error, fmt, str::FromStr};
+
+/// What makes a request invalid, see `Error::Parse`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InvalidHttpRequestError {
+    /// The bytes received don't form an HTTP message at all.
+    Malformed,
+    /// The connection closed before the request was complete.
+    Incomplete,
+    /// The request line isn't a method, a target and a version separated by
+    /// single spaces.
+    InvalidRequestLine,
+    /// The method is well-formed but not one this server knows.
+    UnknownMethod,
+    /// The request target isn't a valid URL.
+    InvalidUrl,
+    /// The version is well-formed but not one this server speaks.
+    UnsupportedVersion,
+    /// A header or trailer field isn't a valid `name: value` line.
+    InvalidHeader,
+    /// `Content-Length` isn't a single decimal number.
+    InvalidContentLength,
+    /// Both `Content-Length` and `Transfer-Encoding` were sent.
+    AmbiguousFraming,
+    /// `Transfer-Encoding` is something other than `chunked`.
+    UnsupportedTransferEncoding,
+    /// `Transfer-Encoding` was sent with an HTTP/1.0 request.
+    TransferEncodingInHttp10,
+    /// The chunked body doesn't follow the chunked encoding.
+    InvalidChunk,
+}
 
-#[derive(Debug)]
-pub struct InvalidHttpRequestError();
+impl InvalidHttpRequestError {
+    /// The status the client is answered with.
+    pub fn status(&self) -> Status {
+        match self {
+            InvalidHttpRequestError::UnknownMethod
+            | InvalidHttpRequestError::UnsupportedTransferEncoding => Status::NotImplemented,
+            InvalidHttpRequestError::UnsupportedVersion => Status::HTTPVersionNotSupported,
+            _ => Status::BadRequest,
+        }
+    }
+}
 
 impl Error for InvalidHttpRequestError {}
 
 impl fmt::Display for InvalidHttpRequestError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Invalid Http Request!")
+        let reason = match self {
+            InvalidHttpRequestError::Malformed => "Invalid Http Request!",
+            InvalidHttpRequestError::Incomplete => "Connection closed mid-request!",
+            InvalidHttpRequestError::InvalidRequestLine => "Invalid request line!",
+            InvalidHttpRequestError::UnknownMethod => "Unknown method!",
+            InvalidHttpRequestError::InvalidUrl => "Invalid url!",
+            InvalidHttpRequestError::UnsupportedVersion => "HTTP version not supported!",
+            InvalidHttpRequestError::InvalidHeader => "Invalid header field!",
+            InvalidHttpRequestError::InvalidContentLength => "Invalid Content-Length!",
+            InvalidHttpRequestError::AmbiguousFraming => {
+                "Both Content-Length and Transfer-Encoding sent!"
+            }
+            InvalidHttpRequestError::UnsupportedTransferEncoding => {
+                "Transfer-Encoding not supported!"
+            }
+            InvalidHttpRequestError::TransferEncodingInHttp10 => {
+                "Transfer-Encoding not allowed in HTTP/1.0!"
+            }
+            InvalidHttpRequestError::InvalidChunk => "Invalid chunked body!",
+        };
+        write!(f, "{}", reason)
     }
 }
 
@@ -19,6 +87,14 @@ pub struct Request {
     pub http_version: Version,
     pub header: Header,
     pub body: Body,
+    /// Trailer fields sent after a chunked body.
+    pub trailer: Header,
+    /// Path parameters captured by a `Router`.
+    pub params: HashMap<String, String>,
+    /// Path prefix stripped by the routers this request was mounted through.
+    pub base_path: String,
+    /// The connecting process, for requests received over a Unix socket.
+    pub peer_credentials: Option<PeerCredentials>,
 }
 
 impl Request {
@@ -35,67 +111,70 @@ impl Request {
             http_version,
             header,
             body,
+            trailer: Header::default(),
+            params: HashMap::new(),
+            base_path: String::new(),
+            peer_credentials: None,
         }
     }
 }
 
 impl Request {
-    fn create_header(header_lines: Vec<&str>) -> Result<Header, InvalidHttpRequestError> {
-        let lines = header_lines
-            .iter()
-            .skip(1)
-            .map(|s| *s)
-            .collect::<Vec<&str>>();
-        Header::from_lines(lines).map_err(|_| InvalidHttpRequestError())
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(|value| &value[..])
     }
 
-    fn create_method(method: &str) -> Result<Method, InvalidHttpRequestError> {
-        Method::from_str(method).map_err(|_| InvalidHttpRequestError())
+    /// HTTP/1.1 connections are persistent unless the client asks otherwise,
+    /// HTTP/1.0 ones only if it sends `Connection: keep-alive`.
+    pub fn wants_keep_alive(&self) -> bool {
+        let values = self.header.list("Connection").unwrap_or_default();
+        let has = |token: &str| values.iter().any(|v| v.eq_ignore_ascii_case(token));
+
+        match self.http_version {
+            Version::V1P0 => has("keep-alive") && !has("close"),
+            Version::V1P1 => !has("close"),
+        }
     }
+}
 
-    fn create_url(url: &str) -> Result<URL, InvalidHttpRequestError> {
-        URL::from_str(url).map_err(|_| InvalidHttpRequestError())
+impl Request {
+    /// Any token could be a method, so only a token this server doesn't
+    /// know is answered with 501.
+    pub(super) fn create_method(method: &str) -> Result<Method, InvalidHttpRequestError> {
+        Method::from_str(method).map_err(|_| match header::is_token(method) {
+            true => InvalidHttpRequestError::UnknownMethod,
+            false => InvalidHttpRequestError::InvalidRequestLine,
+        })
     }
 
-    fn create_http_version(http_version: &str) -> Result<Version, InvalidHttpRequestError> {
-        Version::from_str(http_version).map_err(|_| InvalidHttpRequestError())
+    pub(super) fn create_url(url: &str) -> Result<URL, InvalidHttpRequestError> {
+        URL::from_str(url).map_err(|_| InvalidHttpRequestError::InvalidUrl)
     }
 
-    fn create_body(body: &str) -> Result<Body, InvalidHttpRequestError> {
-        Body::from_str(body).map_err(|_| InvalidHttpRequestError())
+    /// Only a well-formed `HTTP/x.y` version is answered with 505.
+    pub(super) fn create_http_version(
+        http_version: &str,
+    ) -> Result<Version, InvalidHttpRequestError> {
+        let is_version = match http_version.strip_prefix("HTTP/").map(str::as_bytes) {
+            Some([major, b'.', minor]) => major.is_ascii_digit() && minor.is_ascii_digit(),
+            _ => false,
+        };
+
+        Version::from_str(http_version).map_err(|_| match is_version {
+            true => InvalidHttpRequestError::UnsupportedVersion,
+            false => InvalidHttpRequestError::InvalidRequestLine,
+        })
     }
 }
 
 impl FromStr for Request {
-    type Err = InvalidHttpRequestError;
+    type Err = crate::error::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let headers_and_body = s.split("\r\n\r\n").collect::<Vec<&str>>();
-
-        let headers = headers_and_body.first().ok_or(InvalidHttpRequestError())?;
-        let body = headers_and_body.last().ok_or(InvalidHttpRequestError())?;
-
-        let header_lines = headers.split("\r\n").collect::<Vec<&str>>();
-        let fst_header_line = header_lines
-            .first()
-            .ok_or(InvalidHttpRequestError())?
-            .split(" ")
-            .collect::<Vec<&str>>();
-
-        if fst_header_line.len() < 3 {
-            return Err(InvalidHttpRequestError());
+        let mut parser = RequestParser::new();
+        match parser.feed(s.as_bytes())? {
+            ParseStatus::Complete(req) => Ok(req),
+            ParseStatus::Incomplete => Err(parser.incomplete()),
         }
-
-        let (method_str, url_str, http_version_str) =
-            (fst_header_line[0], fst_header_line[1], fst_header_line[2]);
-
-        let method = Request::create_method(method_str)?;
-        let url = Request::create_url(url_str)?;
-        let http_version = Request::create_http_version(http_version_str)?;
-        let header = Request::create_header(header_lines)?;
-        let body = Request::create_body(body)?;
-
-        let req = Request::new(method, url, http_version, header, body);
-        Ok(req)
     }
 }
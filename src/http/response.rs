@@ -1,5 +1,10 @@
 use super::{body::Body, header::Header, status::Status, version::Version};
-use std::path::Path;
+use crate::net::socket::Socket;
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+};
 
 #[derive(Default)]
 pub struct Response {
@@ -17,10 +22,10 @@ impl Response {
     pub fn build_headers_string(&self) -> String {
         format!(
             "{} {} {}\r\n{}",
-            self.http_version.to_string(),
+            self.http_version,
             self.status.get_code(),
-            self.status.to_string(),
-            self.header.to_string(),
+            self.status,
+            self.header,
         )
     }
 
@@ -29,6 +34,47 @@ impl Response {
         res.extend(self.body.get());
         res
     }
+
+    /// Whether the body goes out with `Transfer-Encoding: chunked`.
+    pub fn is_chunked(&self) -> bool {
+        match self.header.list("Transfer-Encoding") {
+            Some(encodings) => encodings
+                .last()
+                .is_some_and(|e| e.eq_ignore_ascii_case("chunked")),
+            None => false,
+        }
+    }
+
+    /// Writes the response to `socket`. Unlike `write_to` this lets file
+    /// bodies go out through `sendfile(2)`.
+    pub fn send_to(self, socket: &Socket) -> io::Result<()> {
+        if self.is_chunked() || !self.body.is_file() {
+            let mut writer = io::BufWriter::new(socket);
+            self.write_to(&mut writer)?;
+            return writer.flush();
+        }
+
+        socket.send_all(self.build_headers_string().as_bytes())?;
+        self.body.send_to(socket)
+    }
+
+    /// Writes only the status line and headers to `socket`, as the answer
+    /// to a HEAD request.
+    pub fn send_head_to(&self, socket: &Socket) -> io::Result<()> {
+        socket.send_all(self.build_headers_string().as_bytes())
+    }
+
+    /// Writes the status line, headers and body to `writer`, streaming the
+    /// body instead of collecting it in memory first.
+    pub fn write_to(self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(self.build_headers_string().as_bytes())?;
+
+        if self.is_chunked() {
+            self.body.write_chunked_to(writer)
+        } else {
+            self.body.write_to(writer)
+        }
+    }
 }
 
 impl From<ResponseBuilder> for Response {
@@ -50,20 +96,130 @@ impl ResponseBuilder {
         self
     }
 
+    /// Streams the body from `reader`. Without a `Content-Length` header the
+    /// response is sent chunked.
+    pub fn stream(mut self, reader: impl Read + Send + 'static) -> Self {
+        self.0.body = Body::from_reader(reader);
+        self
+    }
+
+    /// Streams the body from an iterator of byte chunks.
+    pub fn chunks<I>(mut self, chunks: I) -> Self
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+        I::IntoIter: Send + 'static,
+    {
+        self.0.body = Body::from_chunks(chunks);
+        self
+    }
+
     pub fn header(mut self, key: &str, val: &str) -> Self {
         self.0.header.add(key, val);
         self
     }
 
     pub fn body_with_content_type_and_length(mut self, path: &Path, body: Vec<u8>) -> Self {
-        let content_type = mime_guess::from_path(path)
-            .first_raw()
-            .unwrap_or("text/plain");
-
-        self.0.header.add("Content-Type", content_type);
+        self.0.header.add("Content-Type", content_type(path));
         self.0.header.add("Content-Length", &body.len().to_string());
 
         self.0.body = Body::new(body);
         self
     }
+
+    /// Like `body_with_content_type_and_length`, but the body is sent from
+    /// `file` when the response is written instead of being read up front.
+    pub fn file_with_content_type_and_length(
+        mut self,
+        path: &Path,
+        file: File,
+    ) -> io::Result<Self> {
+        let body = Body::from_file(file)?;
+        let length = body.content_length().unwrap_or(0);
+
+        self.0.header.add("Content-Type", content_type(path));
+        self.0.header.add("Content-Length", &length.to_string());
+
+        self.0.body = body;
+        Ok(self)
+    }
+
+    /// Sends `len` bytes of `file` starting at `offset` as the body.
+    pub fn file_range_with_content_type_and_length(
+        mut self,
+        path: &Path,
+        file: File,
+        offset: u64,
+        len: u64,
+    ) -> Self {
+        self.0.header.add("Content-Type", content_type(path));
+        self.0.header.add("Content-Length", &len.to_string());
+
+        self.0.body = Body::from_file_range(file, offset, len);
+        self
+    }
+}
+
+pub(super) fn content_type(path: &Path) -> &'static str {
+    mime_guess::from_path(path)
+        .first_raw()
+        .unwrap_or("text/plain")
+}
+
+#[cfg(test)]
+mod test_write_to {
+    use super::*;
+
+    fn written(res: Response) -> String {
+        let mut out = vec![];
+        res.write_to(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_is_chunked_should_check_last_transfer_coding() {
+        let vals = [
+            (None, false),
+            (Some("chunked"), true),
+            (Some("Chunked"), true),
+            (Some("gzip, chunked"), true),
+            (Some("chunked, gzip"), false),
+            (Some("gzip"), false),
+        ];
+
+        for (encoding, expected) in vals.iter() {
+            let mut builder = Response::builder();
+            if let Some(encoding) = encoding {
+                builder = builder.header("Transfer-Encoding", encoding);
+            }
+            let res: Response = builder.into();
+            assert_eq!(*expected, res.is_chunked(), "{:?}", encoding);
+        }
+    }
+
+    #[test]
+    fn test_write_to_should_chunk_body_when_chunked() {
+        let res = Response::builder()
+            .header("Transfer-Encoding", "chunked")
+            .chunks(vec![b"hello".to_vec(), b" world".to_vec()])
+            .into();
+
+        assert_eq!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+            written(res)
+        );
+    }
+
+    #[test]
+    fn test_write_to_should_send_body_as_is_when_not_chunked() {
+        let res = Response::builder()
+            .header("Content-Length", "5")
+            .stream(io::Cursor::new(b"hello".to_vec()))
+            .into();
+
+        assert_eq!(
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello",
+            written(res)
+        );
+    }
 }
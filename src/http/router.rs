@@ -0,0 +1,421 @@
+use super::{
+    method::Method, request::Request, response::Response, server::Handler, status::Status,
+    url::percent_decode,
+};
+use crate::error::Error;
+use std::sync::Arc;
+
+type BoxedHandler = Arc<dyn Fn(Request) -> Result<Response, Error> + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Static(String),
+    Param(String),
+    Rest(String),
+}
+
+#[derive(Clone)]
+enum Target {
+    /// Serves requests with this method on exactly the pattern.
+    Endpoint(Method),
+    /// Serves every request below the pattern, with the prefix stripped.
+    Mount,
+}
+
+#[derive(Clone)]
+struct Route {
+    target: Target,
+    pattern: Vec<Segment>,
+    handler: BoxedHandler,
+}
+
+/// Dispatches requests to handlers by method and path.
+///
+/// Patterns are made of `/` separated segments. A `:name` segment captures
+/// one path segment, a trailing `*name` segment captures the rest of the
+/// path. Captures end up in `Request::params`; single segments are
+/// percent-decoded, the rest is passed on as is.
+///
+/// Routes are tried in the order they were added and the first match wins.
+/// GET routes answer HEAD requests as well.
+/// A path matching only routes for other methods gets `405 Method Not
+/// Allowed` with an `Allow` header, any other path `404 Not Found`.
+#[derive(Clone, Default)]
+pub struct Router {
+    routes: Arc<Vec<Route>>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route(self, method: Method, pattern: &str, handler: impl Handler) -> Self {
+        self.add(Target::Endpoint(method), parse_pattern(pattern), handler)
+    }
+
+    pub fn get(self, pattern: &str, handler: impl Handler) -> Self {
+        self.route(Method::GET, pattern, handler)
+    }
+
+    pub fn post(self, pattern: &str, handler: impl Handler) -> Self {
+        self.route(Method::POST, pattern, handler)
+    }
+
+    pub fn put(self, pattern: &str, handler: impl Handler) -> Self {
+        self.route(Method::PUT, pattern, handler)
+    }
+
+    pub fn patch(self, pattern: &str, handler: impl Handler) -> Self {
+        self.route(Method::PATCH, pattern, handler)
+    }
+
+    pub fn delete(self, pattern: &str, handler: impl Handler) -> Self {
+        self.route(Method::DELETE, pattern, handler)
+    }
+
+    /// Hands every request below `prefix` to `handler`, e.g. another
+    /// `Router` or a `FileServer`. The handler sees the path with the prefix
+    /// removed, the removed part is appended to `Request::base_path`.
+    pub fn mount(self, prefix: &str, handler: impl Handler) -> Self {
+        let pattern = parse_pattern(prefix);
+        if let Some(Segment::Rest(_)) = pattern.last() {
+            panic!("Mount prefix {:?} can't end with a wildcard!", prefix);
+        }
+
+        self.add(Target::Mount, pattern, handler)
+    }
+
+    fn add(mut self, target: Target, pattern: Vec<Segment>, handler: impl Handler) -> Self {
+        let handler: BoxedHandler = Arc::new(move |req| handler.serve_http(req));
+        Arc::make_mut(&mut self.routes).push(Route {
+            target,
+            pattern,
+            handler,
+        });
+        self
+    }
+}
+
+impl Route {
+    /// Matches `path` against the pattern. Returns the captured parameters
+    /// and the unmatched rest of the path, which is only non-empty for
+    /// mounts.
+    fn capture<'a>(&self, path: &'a str) -> Option<(Vec<(String, String)>, &'a str)> {
+        let mut params = vec![];
+        let mut rest = path;
+
+        for segment in self.pattern.iter() {
+            let trimmed = rest.strip_prefix('/').unwrap_or(rest);
+
+            if let Segment::Rest(name) = segment {
+                params.push((name.clone(), trimmed.to_string()));
+                rest = "";
+                break;
+            }
+
+            let (head, tail) = match trimmed.find('/') {
+                Some(slash) => trimmed.split_at(slash),
+                None => (trimmed, ""),
+            };
+
+            match segment {
+                Segment::Static(name) if name == head => {}
+                Segment::Param(name) if !head.is_empty() => {
+                    let value = String::from_utf8(percent_decode(head)?).ok()?;
+                    params.push((name.clone(), value));
+                }
+                _ => return None,
+            }
+
+            rest = tail;
+        }
+
+        match self.target {
+            Target::Mount => Some((params, rest)),
+            Target::Endpoint(_) if rest.is_empty() || rest == "/" => Some((params, "")),
+            Target::Endpoint(_) => None,
+        }
+    }
+}
+
+impl Handler for Router {
+    fn serve_http(&self, mut req: Request) -> Result<Response, Error> {
+        let mut allowed: Vec<Method> = vec![];
+
+        for route in self.routes.iter() {
+            let (params, rest) = match route.capture(&req.url.path) {
+                Some((params, rest)) => (params, rest.to_string()),
+                None => continue,
+            };
+
+            if let Target::Endpoint(method) = route.target {
+                if !serves(method, req.method) {
+                    let mut allow = |method| {
+                        if !allowed.contains(&method) {
+                            allowed.push(method);
+                        }
+                    };
+                    allow(method);
+                    if method == Method::GET {
+                        allow(Method::HEAD);
+                    }
+                    continue;
+                }
+            }
+
+            req.params.extend(params);
+            if let Target::Mount = route.target {
+                let consumed = &req.url.path[..req.url.path.len() - rest.len()];
+                req.base_path.push_str(consumed.trim_end_matches('/'));
+                req.url.path = if rest.is_empty() {
+                    String::from("/")
+                } else {
+                    rest
+                };
+            }
+
+            return (route.handler)(req);
+        }
+
+        if allowed.is_empty() {
+            return Ok(error_response(Status::NotFound).into());
+        }
+
+        let allow = allowed
+            .iter()
+            .map(Method::as_str)
+            .collect::<Vec<&str>>()
+            .join(", ");
+        Ok(error_response(Status::MethodNotAllowed)
+            .header("Allow", &allow)
+            .into())
+    }
+}
+
+/// A GET route also answers HEAD requests, the server drops the body
+/// (RFC 9110, 9.3.2).
+fn serves(route_method: Method, method: Method) -> bool {
+    route_method == method || (route_method == Method::GET && method == Method::HEAD)
+}
+
+fn error_response(status: Status) -> super::response::ResponseBuilder {
+    let body = format!("{} {}", status.get_code(), status).into_bytes();
+
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(body)
+}
+
+/// Splits a route pattern into segments. Invalid patterns are programming
+/// errors, so they panic while the router is being built.
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    if !pattern.starts_with('/') {
+        panic!("Route pattern {:?} must start with '/'!", pattern);
+    }
+
+    let parts = pattern
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>();
+
+    parts
+        .iter()
+        .enumerate()
+        .map(|(i, part)| {
+            if let Some(name) = part.strip_prefix(':') {
+                if name.is_empty() {
+                    panic!("Route pattern {:?} has an unnamed parameter!", pattern);
+                }
+                Segment::Param(name.to_string())
+            } else if let Some(name) = part.strip_prefix('*') {
+                if i != parts.len() - 1 {
+                    panic!("Route pattern {:?} has a wildcard before its end!", pattern);
+                }
+                Segment::Rest(name.to_string())
+            } else {
+                Segment::Static(part.to_string())
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test_router {
+    use super::*;
+    use std::str::FromStr;
+
+    fn echo(name: &'static str) -> impl Handler {
+        move |req: Request| {
+            let mut params = req
+                .params
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<String>>();
+            params.sort();
+            let body = format!(
+                "{} {} {} {}",
+                name,
+                req.base_path,
+                req.url.path,
+                params.join("&")
+            );
+
+            Ok(Response::builder().body(body.trim_end().into()).into())
+        }
+    }
+
+    fn serve(router: &Router, request_line: &str) -> Response {
+        let req = Request::from_str(&format!("{}\r\n\r\n", request_line)).unwrap();
+        router.serve_http(req).unwrap()
+    }
+
+    fn body(res: Response) -> String {
+        String::from_utf8(res.body.get()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_pattern() {
+        assert_eq!(Vec::<Segment>::new(), parse_pattern("/"));
+        assert_eq!(
+            vec![
+                Segment::Static(String::from("users")),
+                Segment::Param(String::from("id")),
+                Segment::Rest(String::from("rest")),
+            ],
+            parse_pattern("/users/:id/*rest")
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_parse_pattern_should_panic_on_early_wildcard() {
+        parse_pattern("/static/*rest/more");
+    }
+
+    #[test]
+    fn test_router_should_capture_params() {
+        let router = Router::new()
+            .get("/", echo("index"))
+            .get("/users/:id", echo("user"))
+            .get("/users/:id/posts/:post", echo("post"))
+            .get("/static/*rest", echo("static"));
+
+        let vals = [
+            ("GET / HTTP/1.1", "index  /"),
+            ("GET /users/42 HTTP/1.1", "user  /users/42 id=42"),
+            ("GET /users/42/ HTTP/1.1", "user  /users/42/ id=42"),
+            (
+                "GET /users/j%C3%B6rg HTTP/1.1",
+                "user  /users/j%C3%B6rg id=jörg",
+            ),
+            (
+                "GET /users/7/posts/9 HTTP/1.1",
+                "post  /users/7/posts/9 id=7&post=9",
+            ),
+            (
+                "GET /static/css/a.css HTTP/1.1",
+                "static  /static/css/a.css rest=css/a.css",
+            ),
+            ("GET /static HTTP/1.1", "static  /static rest="),
+        ];
+
+        for (request_line, expected) in vals.iter() {
+            let res = serve(&router, request_line);
+            assert_eq!(200, res.status.get_code());
+            assert_eq!(*expected, body(res));
+        }
+    }
+
+    #[test]
+    fn test_router_should_answer_head_with_get_routes() {
+        let router = Router::new()
+            .route(Method::HEAD, "/ping", echo("head"))
+            .get("/ping", echo("ping"))
+            .get("/users/:id", echo("get"))
+            .post("/users", echo("create"));
+
+        assert_eq!(
+            "get  /users/1 id=1",
+            body(serve(&router, "HEAD /users/1 HTTP/1.1"))
+        );
+        assert_eq!("head  /ping", body(serve(&router, "HEAD /ping HTTP/1.1")));
+        assert_eq!("ping  /ping", body(serve(&router, "GET /ping HTTP/1.1")));
+
+        let res = serve(&router, "HEAD /users HTTP/1.1");
+        assert_eq!(405, res.status.get_code());
+        assert_eq!(Some(String::from("POST")), res.header.combined("Allow"));
+    }
+
+    #[test]
+    fn test_router_should_answer_404_and_405() {
+        let router = Router::new()
+            .get("/users/:id", echo("get"))
+            .delete("/users/:id", echo("delete"))
+            .post("/users", echo("create"));
+
+        let vals = [
+            "GET /posts HTTP/1.1",
+            "GET /users/1/2 HTTP/1.1",
+            "GET /userss/1 HTTP/1.1",
+        ];
+        for request_line in vals.iter() {
+            assert_eq!(404, serve(&router, request_line).status.get_code());
+        }
+
+        let res = serve(&router, "PUT /users/1 HTTP/1.1");
+        assert_eq!(405, res.status.get_code());
+        assert_eq!(
+            Some(String::from("GET, HEAD, DELETE")),
+            res.header.combined("Allow")
+        );
+        let res = serve(&router, "GET /users HTTP/1.1");
+        assert_eq!(405, res.status.get_code());
+        assert_eq!(Some(String::from("POST")), res.header.combined("Allow"));
+
+        assert_eq!(
+            "delete  /users/1 id=1",
+            body(serve(&router, "DELETE /users/1 HTTP/1.1"))
+        );
+    }
+
+    #[test]
+    fn test_router_should_mount_handlers_under_prefix() {
+        let posts = Router::new()
+            .get("/", echo("posts"))
+            .get("/:post", echo("post"));
+        let router = Router::new()
+            .get("/api/health", echo("health"))
+            .mount("/api/users/:id/posts", posts)
+            .mount("/assets", echo("assets"));
+
+        let vals = [
+            ("GET /api/health HTTP/1.1", "health  /api/health"),
+            (
+                "GET /api/users/3/posts HTTP/1.1",
+                "posts /api/users/3/posts / id=3",
+            ),
+            (
+                "GET /api/users/3/posts/8 HTTP/1.1",
+                "post /api/users/3/posts /8 id=3&post=8",
+            ),
+            ("POST /assets/a/b.js HTTP/1.1", "assets /assets /a/b.js"),
+        ];
+
+        for (request_line, expected) in vals.iter() {
+            assert_eq!(*expected, body(serve(&router, request_line)));
+        }
+
+        assert_eq!(
+            404,
+            serve(&router, "GET /assetsx HTTP/1.1").status.get_code()
+        );
+        let res = serve(&router, "POST /api/users/3/posts/8 HTTP/1.1");
+        assert_eq!(405, res.status.get_code());
+        assert_eq!(
+            Some(String::from("GET, HEAD")),
+            res.header.combined("Allow")
+        );
+    }
+}
@@ -1,68 +1,960 @@
-use super::{request::Request, response::Response, thread_pool::ThreadPool};
-use crate::net::socket::Socket;
-use std::{io, str::FromStr, time};
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use super::event_loop;
+use super::{
+    body::Body,
+    builder::HttpServerBuilder,
+    method::Method,
+    parser::{Limits, ParseStatus, RequestParser},
+    request::Request,
+    response::Response,
+    shutdown::{Shutdown, TrackedSocket},
+    status::Status,
+    thread_pool::ThreadPool,
+    version::Version,
+};
+use crate::{
+    error::Error,
+    net::socket::{PeerCredentials, Socket},
+};
+use std::{
+    fs, io,
+    net::{SocketAddr, ToSocketAddrs},
+    os::unix::io::AsRawFd,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread, time,
+};
 
+/// Answers requests. A returned `Error` is answered with its status, see
+/// `Error::status`, and closes the connection.
 pub trait Handler: Clone + Send + Sync + 'static {
-    fn serve_http(&self, req: Request) -> io::Result<Response>;
+    fn serve_http(&self, req: Request) -> Result<Response, Error>;
 }
 
 impl<F> Handler for F
 where
-    F: Fn(Request) -> io::Result<Response> + Clone + Send + Sync + 'static,
+    F: Fn(Request) -> Result<Response, Error> + Clone + Send + Sync + 'static,
 {
-    fn serve_http(&self, req: Request) -> io::Result<Response> {
+    fn serve_http(&self, req: Request) -> Result<Response, Error> {
         self(req)
     }
 }
 
+/// Controls how long a persistent connection may hold on to a worker.
+#[derive(Debug, Clone, Copy)]
+pub struct KeepAlive {
+    /// How long to wait for the next request before closing the connection.
+    pub timeout: time::Duration,
+    /// How many requests to serve before closing the connection.
+    pub max_requests: usize,
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        Self {
+            timeout: time::Duration::from_secs(5),
+            max_requests: 100,
+        }
+    }
+}
+
+/// How long a client may take over each part of a request before it is
+/// answered with `408 Request Timeout`. The wait for the next request on a
+/// persistent connection is `KeepAlive::timeout`.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// How long the whole head of a request may take to arrive, counted
+    /// from its first byte. A new connection gets as long to send that byte.
+    pub header: time::Duration,
+    /// Longest pause while the body of a request is coming in.
+    pub body: time::Duration,
+    /// The longest a single write to the client may block before the
+    /// connection is dropped.
+    pub write: time::Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            header: time::Duration::from_secs(10),
+            body: time::Duration::from_secs(30),
+            write: time::Duration::from_secs(30),
+        }
+    }
+}
+
+/// How connections are spread over the worker threads.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Dispatch {
+    /// Every connection holds on to a worker for as long as it stays open.
+    #[default]
+    PerConnection,
+    /// An epoll event loop watches all connections and only hands complete
+    /// requests to the workers, so idle keep-alive connections cost no
+    /// thread.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    EventLoop,
+}
+
+/// What to do with new work while every worker is busy and the pool's queue
+/// is full.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OverloadPolicy {
+    /// Stop accepting until a worker frees up. Clients wait in the listen
+    /// backlog.
+    ///
+    /// `Dispatch::EventLoop` can't stop to wait without stalling every other
+    /// connection, so it answers like `Reject` with a one second
+    /// `Retry-After` instead.
+    #[default]
+    Block,
+    /// Answer with `503 Service Unavailable` right away, telling the client
+    /// to come back after `retry_after`.
+    Reject { retry_after: time::Duration },
+}
+
+/// Settings shared by every connection, fixed once the server is built.
+/// See `HttpServerBuilder` for what each of them means.
+#[derive(Debug, Clone)]
+pub(super) struct Config {
+    pub(super) backlog: usize,
+    pub(super) read_buffer_size: usize,
+    pub(super) keep_alive: KeepAlive,
+    pub(super) timeouts: Timeouts,
+    pub(super) limits: Limits,
+    pub(super) server_name: Option<String>,
+    pub(super) dispatch: Dispatch,
+    pub(super) overload_policy: OverloadPolicy,
+    pub(super) shutdown_timeout: time::Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            backlog: 128,
+            read_buffer_size: 30000,
+            keep_alive: KeepAlive::default(),
+            timeouts: Timeouts::default(),
+            limits: Limits::default(),
+            server_name: None,
+            dispatch: Dispatch::default(),
+            overload_policy: OverloadPolicy::default(),
+            shutdown_timeout: time::Duration::from_secs(30),
+        }
+    }
+}
+
+impl Config {
+    /// A plain text response for `status` in `version`, with `detail` after
+    /// the reason phrase if the client should know more.
+    pub(super) fn error_response(
+        &self,
+        status: Status,
+        detail: Option<String>,
+        version: Version,
+    ) -> Response {
+        let mut body = format!("{} {}", status.get_code(), status);
+        if let Some(detail) = detail {
+            body = format!("{}: {}", body, detail);
+        }
+        let body = body.into_bytes();
+        let mut res: Response = Response::builder()
+            .status(status)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(body)
+            .into();
+
+        self.prepare_response(&mut res, version, false);
+        res
+    }
+
+    /// Answers in the client's `version` and makes sure it can find the end
+    /// of the response on a persistent connection. Tells the client whether
+    /// the connection stays open and returns that.
+    fn prepare_response(&self, res: &mut Response, version: Version, persist: bool) -> bool {
+        let mut persist = persist;
+        res.http_version = version;
+
+        if !res.status.has_body() {
+            // Like the answer to a HEAD request, these end with the header.
+            res.body = Body::default();
+            res.header.del("Content-Length");
+            res.header.del("Transfer-Encoding");
+        } else {
+            // HTTP/1.0 clients don't know chunked encoding.
+            if version == Version::V1P0 && res.is_chunked() {
+                let encodings = res.header.list("Transfer-Encoding").unwrap_or_default();
+                let others = encodings
+                    .iter()
+                    .filter(|encoding| !encoding.eq_ignore_ascii_case("chunked"))
+                    .map(String::as_str)
+                    .collect::<Vec<&str>>();
+                res.header.del("Transfer-Encoding");
+                res.header.add("Transfer-Encoding", &others.join(", "));
+            }
+
+            if res.is_chunked() {
+                // The chunks frame the body; a length next to them could only
+                // disagree.
+                res.header.del("Content-Length");
+            } else if res.header.get("Content-Length").is_none() {
+                match (res.body.content_length(), version) {
+                    (Some(length), _) => res.header.add("Content-Length", &length.to_string()),
+                    (None, Version::V1P1) => res.header.add("Transfer-Encoding", "chunked"),
+                    // Closing the connection is what ends the body.
+                    (None, Version::V1P0) => persist = false,
+                }
+            }
+        }
+
+        res.header.del("Connection");
+        let connection = if persist { "keep-alive" } else { "close" };
+        res.header.add("Connection", connection);
+
+        if let Some(server_name) = self.server_name.as_ref() {
+            res.header.del("Server");
+            res.header.add("Server", server_name);
+        }
+
+        persist
+    }
+}
+
+/// What `Overload::shed_nonblocking` tells clients under
+/// `OverloadPolicy::Block`.
+const BLOCK_RETRY_AFTER: time::Duration = time::Duration::from_secs(1);
+
+/// Turns connections away while the pool is full, if the overload policy
+/// says so, and counts them.
+#[derive(Default)]
+pub(super) struct Overload {
+    shed: AtomicUsize,
+}
+
+impl Overload {
+    /// Answers with a 503 instead of queueing the connection when the pool
+    /// has no room and the policy says so. Returns whether it did.
+    pub(super) fn shed(&self, client_socket: &Socket, pool: &ThreadPool, config: &Config) -> bool {
+        match config.overload_policy {
+            OverloadPolicy::Reject { retry_after } if pool.is_full() => {
+                self.reject(client_socket, config, retry_after);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Like `shed`, but for callers that must not block: a full pool is
+    /// shed under `OverloadPolicy::Block` too.
+    pub(super) fn shed_nonblocking(
+        &self,
+        client_socket: &Socket,
+        pool: &ThreadPool,
+        config: &Config,
+    ) -> bool {
+        if !pool.is_full() {
+            return false;
+        }
+        let retry_after = match config.overload_policy {
+            OverloadPolicy::Block => BLOCK_RETRY_AFTER,
+            OverloadPolicy::Reject { retry_after } => retry_after,
+        };
+        self.reject(client_socket, config, retry_after);
+        true
+    }
+
+    fn reject(&self, client_socket: &Socket, config: &Config, retry_after: time::Duration) {
+        let shed = self.shed.fetch_add(1, Ordering::SeqCst) + 1;
+        warn!("Worker queue full, shedding connection ({} so far)", shed);
+
+        discard_unread(client_socket, config);
+        let mut res = config.error_response(Status::ServiceUnavailable, None, Version::default());
+        let seconds = retry_after.as_secs() + (retry_after.subsec_nanos() > 0) as u64;
+        res.header.add("Retry-After", &seconds.to_string());
+        if let Err(e) = res.send_to(client_socket) {
+            debug!("Couldn't send 503: {}", e);
+        }
+    }
+}
+
+/// Reads away whatever the client already sent. Unread request bytes would
+/// make closing the socket reset the connection before the client got to
+/// read the response.
+fn discard_unread(client_socket: &Socket, config: &Config) {
+    let _ = client_socket.set_nonblocking(true);
+    let _ = client_socket.receive(&mut vec![0; config.read_buffer_size]);
+}
+
 pub struct HttpServer {
-    port: u16,
-    socket: Socket,
+    listeners: Vec<Socket>,
+    /// Unix socket files to remove once the server is gone.
+    socket_files: Vec<PathBuf>,
     pool: ThreadPool,
+    overload: Overload,
+    config: Arc<Config>,
+    shutdown: Shutdown,
 }
 
 impl HttpServer {
+    /// Configures a server step by step, see `HttpServerBuilder`.
+    pub fn builder() -> HttpServerBuilder {
+        HttpServerBuilder::new()
+    }
+
+    /// A server only reachable from this host, on `127.0.0.1:port`.
     pub fn new(port: u16) -> io::Result<Self> {
-        let socket = Socket::new()?;
-        socket.bind(port)?;
-        let pool = ThreadPool::new(4);
+        HttpServer::bind(("127.0.0.1", port))
+    }
+
+    /// A server listening on every address `addrs` resolves to. IPv6
+    /// sockets are dual-stack, so `[::]:8080` accepts IPv4 clients too.
+    pub fn bind(addrs: impl ToSocketAddrs) -> io::Result<Self> {
+        HttpServer::builder().bind(addrs).build()
+    }
+
+    /// Like `bind`, but IPv6 sockets only accept IPv6 clients. This allows
+    /// binding `0.0.0.0` and `::` on the same port.
+    pub fn bind_v6_only(addrs: impl ToSocketAddrs) -> io::Result<Self> {
+        HttpServer::builder().bind(addrs).only_v6(true).build()
+    }
+
+    /// A server listening on a Unix domain socket at `path`, with the socket
+    /// file's permission bits set to `mode`. A socket file left behind by a
+    /// server that is gone is replaced, one still in use is an error.
+    pub fn bind_unix(path: impl AsRef<Path>, mode: u32) -> io::Result<Self> {
+        HttpServer::builder().bind_unix(path, mode).build()
+    }
+
+    /// A server listening on a Unix domain socket in the abstract namespace.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub fn bind_abstract(name: &str) -> io::Result<Self> {
+        HttpServer::builder().bind_abstract(name).build()
+    }
+
+    pub(super) fn with_parts(
+        listeners: Vec<Socket>,
+        socket_files: Vec<PathBuf>,
+        pool: ThreadPool,
+        config: Config,
+    ) -> Self {
+        let shutdown = Shutdown::new(listeners.iter().map(Socket::as_raw_fd).collect());
+
+        Self {
+            listeners,
+            socket_files,
+            pool,
+            overload: Overload::default(),
+            config: Arc::new(config),
+            shutdown,
+        }
+    }
+
+    /// The TCP addresses the server is bound to, with the actual ports when
+    /// bound to port 0.
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.listeners
+            .iter()
+            .filter(|listener| !listener.is_unix())
+            .map(Socket::local_addr)
+            .collect()
+    }
 
-        Ok(Self { port, socket, pool })
+    /// How many workers are running right now.
+    pub fn pool_size(&self) -> usize {
+        self.pool.size()
+    }
+
+    /// How many connections, or requests with `Dispatch::EventLoop`, are
+    /// waiting for a worker right now.
+    pub fn queue_len(&self) -> usize {
+        self.pool.queue_len()
+    }
+
+    /// How many connections were answered with a 503 because the queue was
+    /// full.
+    pub fn shed_count(&self) -> usize {
+        self.overload.shed.load(Ordering::SeqCst)
+    }
+
+    /// How many times a handler panicked. The client gets a 500 and the
+    /// worker moves on to the next connection.
+    pub fn panic_count(&self) -> usize {
+        self.pool.panic_count()
+    }
+
+    /// A handle that makes `listen_and_serve` stop and return.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Shuts the server down gracefully on SIGINT, SIGTERM or SIGHUP. Only
+    /// one such handler can be installed per process.
+    pub fn shutdown_on_signal(&self) -> io::Result<()> {
+        let shutdown = self.shutdown_handle();
+        ctrlc::set_handler(move || shutdown.shutdown()).map_err(io::Error::other)
     }
 
     pub fn listen_and_serve(&self, handler: impl Handler) -> io::Result<()> {
-        self.socket.listen(128)?;
-        info!("Server started on port: {}", self.port);
+        for listener in self.listeners.iter() {
+            listener.listen(self.config.backlog)?;
+            if listener.is_unix() {
+                info!("Server listening on a Unix socket");
+            } else {
+                info!("Server listening on: {}", listener.local_addr()?);
+            }
+        }
+
+        let result = match self.config.dispatch {
+            Dispatch::PerConnection => self.accept_on_threads(handler),
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            Dispatch::EventLoop => event_loop::run(
+                &self.listeners,
+                &self.pool,
+                &self.overload,
+                handler,
+                &self.config,
+                &self.shutdown,
+            ),
+        };
+        if result.is_err() {
+            self.shutdown.shutdown();
+        }
+
+        info!("Shutting down server");
+        let cut_off = self.shutdown.drain(self.config.shutdown_timeout);
+        if cut_off > 0 {
+            warn!(
+                "Closed {} connections still busy after the shutdown timeout",
+                cut_off
+            );
+        }
+
+        result
+    }
+
+    /// Runs one accept loop per listener, each on its own thread.
+    fn accept_on_threads(&self, handler: impl Handler) -> io::Result<()> {
+        thread::scope(|scope| {
+            let accept_threads = self
+                .listeners
+                .iter()
+                .map(|listener| {
+                    let handler = handler.clone();
+                    scope.spawn(move || self.accept_connections(listener, handler))
+                })
+                .collect::<Vec<_>>();
+
+            accept_threads
+                .into_iter()
+                .map(|accept_thread| accept_thread.join().expect("Accept thread panicked"))
+                .collect::<io::Result<Vec<()>>>()
+                .map(drop)
+        })
+    }
+
+    /// Hands connections from `listener` to the pool until a shutdown is
+    /// requested. A fatal error shuts the other listeners down too.
+    fn accept_connections(&self, listener: &Socket, handler: impl Handler) -> io::Result<()> {
+        for client_socket in listener.incoming() {
+            if self.shutdown.is_requested() {
+                break;
+            }
+
+            let client_socket = match client_socket {
+                Ok(client_socket) => self.shutdown.track(client_socket),
+                Err(e) => match HttpServer::check_accept_error(e) {
+                    Ok(()) => continue,
+                    Err(e) => {
+                        error!("Can't accept on socket {}: {}", listener.as_raw_fd(), e);
+                        self.shutdown.shutdown();
+                        return Err(e);
+                    }
+                },
+            };
+
+            info!("Got a new connection");
+            if self.overload.shed(&client_socket, &self.pool, &self.config) {
+                continue;
+            }
 
-        for client_socket in self.socket.incoming() {
-            info!("Got a new request");
             let handler = handler.clone();
+            let config = Arc::clone(&self.config);
+            let shutdown = self.shutdown.clone();
 
-            self.pool
-                .execute(move || HttpServer::handle_connection(client_socket, handler));
+            self.pool.execute(move || {
+                HttpServer::handle_connection(client_socket, handler, &config, shutdown)
+            });
         }
 
-        info!("Shutting down server on port: {}", self.port);
         Ok(())
     }
 
-    fn handle_connection(client_socket: Socket, handler: impl Handler) -> io::Result<()> {
-        let now = time::Instant::now();
+    /// Accept errors caused by a single client or by running out of
+    /// resources are logged and don't stop the server.
+    pub(super) fn check_accept_error(e: io::Error) -> io::Result<()> {
+        use nix::errno::Errno;
+
+        match e.raw_os_error().map(Errno::from_i32) {
+            Some(Errno::EINTR) | Some(Errno::ECONNABORTED) | Some(Errno::EPROTO) => {
+                debug!("Accept interrupted: {}", e);
+                Ok(())
+            }
+            Some(Errno::EMFILE) | Some(Errno::ENFILE) | Some(Errno::ENOBUFS)
+            | Some(Errno::ENOMEM) => {
+                error!("Can't accept new connections: {}", e);
+                thread::sleep(time::Duration::from_millis(100));
+                Ok(())
+            }
+            _ => Err(e),
+        }
+    }
 
-        let read_buffer = &mut [0; 30000];
-        client_socket
-            .receive(read_buffer)
-            .expect("Clinet Socket receive");
+    fn handle_connection(
+        client_socket: TrackedSocket,
+        handler: impl Handler,
+        config: &Config,
+        shutdown: Shutdown,
+    ) -> io::Result<()> {
+        let peer_credentials = HttpServer::prepare_socket(&client_socket, config)?;
+        let read_buffer = &mut vec![0; config.read_buffer_size];
+        let mut parser = RequestParser::with_limits(config.limits);
 
-        let req =
-            Request::from_str(&String::from_utf8_lossy(read_buffer)).expect("Request build error");
-        let res = handler.serve_http(req)?;
+        for served in 1..=config.keep_alive.max_requests {
+            if served > 1 && parser.is_idle() && !client_socket.set_idle(true) {
+                debug!("Closing idle connection for shutdown");
+                break;
+            }
 
-        client_socket
-            .send(&res.to_bytes())
-            .expect("Request send error");
+            let idle_timeout = match served {
+                1 => config.timeouts.header,
+                _ => config.keep_alive.timeout,
+            };
+            let read = HttpServer::read_request(
+                &client_socket,
+                &mut parser,
+                read_buffer,
+                idle_timeout,
+                &config.timeouts,
+            );
+
+            let mut req = match read {
+                Ok(Some(req)) => req,
+                Ok(None) => {
+                    debug!("Closing connection after {} requests", served - 1);
+                    break;
+                }
+                Err(Error::Io(e)) => return Err(e),
+                Err(e) => {
+                    return HttpServer::reject(&client_socket, &e, Version::default(), config)
+                }
+            };
+
+            req.peer_credentials = peer_credentials;
+            let may_persist = served < config.keep_alive.max_requests;
+            if !HttpServer::serve_request(
+                &client_socket,
+                &handler,
+                req,
+                may_persist,
+                config,
+                &shutdown,
+            )? {
+                break;
+            }
+        }
 
-        info!("Finished request in {}", now.elapsed().as_millis());
         Ok(())
     }
+
+    /// Applies the per connection socket options. Returns the peer's
+    /// credentials for Unix sockets.
+    pub(super) fn prepare_socket(
+        client_socket: &Socket,
+        config: &Config,
+    ) -> io::Result<Option<PeerCredentials>> {
+        client_socket.set_write_timeout(Some(config.timeouts.write))?;
+        if client_socket.is_unix() {
+            return Ok(client_socket.peer_credentials().ok());
+        }
+
+        client_socket.set_nodelay(true)?;
+        Ok(None)
+    }
+
+    /// Runs `req` through the handler and sends the response. Returns whether
+    /// the connection stays open for another request.
+    pub(super) fn serve_request(
+        client_socket: &Socket,
+        handler: &impl Handler,
+        req: Request,
+        may_persist: bool,
+        config: &Config,
+        shutdown: &Shutdown,
+    ) -> io::Result<bool> {
+        let now = time::Instant::now();
+        let persist = may_persist && req.wants_keep_alive();
+        let version = req.http_version;
+        let is_head = matches!(req.method, Method::HEAD);
+
+        let mut res = match panic::catch_unwind(AssertUnwindSafe(|| handler.serve_http(req))) {
+            Ok(Ok(res)) => res,
+            Ok(Err(e)) => {
+                HttpServer::reject(client_socket, &e, version, config)?;
+                return Ok(false);
+            }
+            Err(panic) => {
+                // Nothing was sent yet, so the client can still be told.
+                let res = config.error_response(Status::InternalServerError, None, version);
+                let _ = res.send_to(client_socket);
+                panic::resume_unwind(panic);
+            }
+        };
+        let persist = persist && !shutdown.is_requested();
+        let persist = config.prepare_response(&mut res, version, persist);
+        if is_head {
+            // The headers describe the body a GET would have gotten.
+            res.send_head_to(client_socket)?;
+        } else {
+            res.send_to(client_socket)?;
+        }
+
+        info!("Finished request in {}", now.elapsed().as_millis());
+        Ok(persist)
+    }
+
+    /// Answers a request that couldn't be read or handled with the status
+    /// matching `e`, and gives up on the connection. `version` is the
+    /// request's, if it got far enough to tell.
+    pub(super) fn reject(
+        client_socket: &Socket,
+        e: &Error,
+        version: Version,
+        config: &Config,
+    ) -> io::Result<()> {
+        warn!("Rejecting request: {}", e);
+        discard_unread(client_socket, config);
+        let res = config.error_response(e.status(), e.detail(), version);
+        res.send_to(client_socket)
+    }
+
+    /// Reads from the socket until the parser yields a full request. Returns
+    /// `None` when the client closes the connection or sends nothing for
+    /// `idle_timeout`, and fails with `Error::Timeout` when a started
+    /// request misses one of the `timeouts`.
+    fn read_request(
+        client_socket: &TrackedSocket,
+        parser: &mut RequestParser,
+        read_buffer: &mut [u8],
+        idle_timeout: time::Duration,
+        timeouts: &Timeouts,
+    ) -> Result<Option<Request>, Error> {
+        let mut read_bytes = 0;
+        let mut deadline = time::Instant::now() + idle_timeout;
+
+        loop {
+            if read_bytes > 0 && parser.is_idle() {
+                client_socket.set_idle(false);
+                deadline = time::Instant::now() + timeouts.header;
+            }
+
+            if let ParseStatus::Complete(req) = parser.feed(&read_buffer[..read_bytes])? {
+                return Ok(Some(req));
+            }
+
+            // The head has a deadline, so dribbling it in byte by byte
+            // doesn't help, while a body only has to keep moving.
+            if parser.is_reading_body() {
+                deadline = time::Instant::now() + timeouts.body;
+            }
+
+            let timeout = deadline.saturating_duration_since(time::Instant::now());
+            if timeout.is_zero() {
+                return HttpServer::timed_out(parser);
+            }
+            client_socket.set_read_timeout(Some(timeout))?;
+
+            read_bytes = match client_socket.receive(read_buffer) {
+                Ok(0) if parser.is_idle() => return Ok(None),
+                Ok(0) => return Err(parser.incomplete()),
+                Ok(read_bytes) => read_bytes,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return HttpServer::timed_out(parser)
+                }
+                Err(e) => return Err(Error::Io(e)),
+            };
+        }
+    }
+
+    /// A connection without a request in progress is simply closed, others
+    /// get a 408.
+    fn timed_out(parser: &RequestParser) -> Result<Option<Request>, Error> {
+        if parser.is_idle() {
+            return Ok(None);
+        }
+        Err(Error::Timeout)
+    }
+}
+
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        for path in self.socket_files.iter() {
+            if let Err(e) = fs::remove_file(path) {
+                warn!("Couldn't remove socket file {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_prepare_response {
+    use super::*;
+
+    fn stream() -> Response {
+        Response::builder()
+            .stream(io::Cursor::new(b"hello".to_vec()))
+            .into()
+    }
+
+    #[test]
+    fn test_prepare_response_should_dechunk_for_http_1_0() {
+        let mut res: Response = Response::builder()
+            .header("Transfer-Encoding", "chunked")
+            .stream(io::Cursor::new(b"hello".to_vec()))
+            .into();
+
+        assert!(!Config::default().prepare_response(&mut res, Version::V1P0, true));
+        assert!(!res.is_chunked());
+        assert_eq!(None, res.header.get("Content-Length"));
+        assert_eq!(Some(String::from("close")), res.header.get("Connection"));
+
+        let mut bytes = vec![];
+        res.write_to(&mut bytes).unwrap();
+        let bytes = String::from_utf8(bytes).unwrap();
+        assert!(bytes.starts_with("HTTP/1.0 200 OK\r\n"), "{}", bytes);
+        assert!(bytes.ends_with("\r\n\r\nhello"), "{}", bytes);
+    }
+
+    #[test]
+    fn test_prepare_response_should_close_after_unknown_length_for_http_1_0() {
+        let config = Config::default();
+
+        let mut res = stream();
+        assert!(!config.prepare_response(&mut res, Version::V1P0, true));
+        assert!(!res.is_chunked());
+        assert_eq!(Some(String::from("close")), res.header.get("Connection"));
+
+        let mut res: Response = Response::builder().body(b"hello".to_vec()).into();
+        assert!(config.prepare_response(&mut res, Version::V1P0, true));
+        assert_eq!(Some(String::from("5")), res.header.get("Content-Length"));
+        assert_eq!(
+            Some(String::from("keep-alive")),
+            res.header.get("Connection")
+        );
+
+        let mut res = stream();
+        assert!(config.prepare_response(&mut res, Version::V1P1, true));
+        assert!(res.is_chunked());
+        assert_eq!(
+            Some(String::from("keep-alive")),
+            res.header.get("Connection")
+        );
+    }
+
+    #[test]
+    fn test_prepare_response_should_only_remove_chunked_for_http_1_0() {
+        let mut res: Response = Response::builder()
+            .header("Transfer-Encoding", "gzip, chunked")
+            .body(b"hello".to_vec())
+            .into();
+
+        Config::default().prepare_response(&mut res, Version::V1P0, true);
+        assert_eq!(
+            Some(String::from("gzip")),
+            res.header.get("Transfer-Encoding")
+        );
+        assert_eq!(Some(String::from("5")), res.header.get("Content-Length"));
+    }
+
+    #[test]
+    fn test_prepare_response_should_drop_content_length_when_chunked() {
+        let mut res: Response = Response::builder()
+            .header("Content-Length", "5")
+            .header("Transfer-Encoding", "chunked")
+            .body(b"hello".to_vec())
+            .into();
+
+        Config::default().prepare_response(&mut res, Version::V1P1, true);
+        assert!(res.is_chunked());
+        assert_eq!(None, res.header.get("Content-Length"));
+    }
+
+    #[test]
+    fn test_prepare_response_should_strip_body_without_one() {
+        for status in [Status::Continue, Status::NoContent, Status::NotModified] {
+            let mut res: Response = Response::builder()
+                .status(status)
+                .header("Content-Length", "5")
+                .header("Transfer-Encoding", "chunked")
+                .body(b"hello".to_vec())
+                .into();
+
+            assert!(Config::default().prepare_response(&mut res, Version::V1P1, true));
+            assert_eq!(None, res.header.get("Content-Length"));
+            assert_eq!(None, res.header.get("Transfer-Encoding"));
+
+            let mut bytes = vec![];
+            res.write_to(&mut bytes).unwrap();
+            assert!(bytes.ends_with(b"\r\n\r\n"), "{:?}", bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_handle_connection {
+    use super::*;
+    use crate::http::{file_server::FileServer, testing};
+    use std::{
+        env, fs,
+        io::{BufReader, Write},
+    };
+
+    fn file_root(name: &str) -> &'static str {
+        let root = env::temp_dir().join(format!("server_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("f.txt"), "0123456789abcdefghij").unwrap();
+        Box::leak(root.to_str().unwrap().to_string().into_boxed_str())
+    }
+
+    #[test]
+    fn test_head_should_not_send_body_on_persistent_connection() {
+        let server = testing::serve(HttpServer::builder(), FileServer::new(file_root("head")));
+        let mut stream = server.connect();
+        stream
+            .write_all(b"HEAD /f.txt HTTP/1.1\r\n\r\nGET /f.txt HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut reader = BufReader::new(stream);
+
+        let head = testing::read_response(&mut reader, true);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+        assert!(head.contains("Content-Length: 20\r\n"), "{}", head);
+        assert!(head.contains("Connection: keep-alive\r\n"), "{}", head);
+
+        let get = testing::read_response(&mut reader, false);
+        assert!(get.starts_with("HTTP/1.1 200 OK\r\n"), "{}", get);
+        assert!(get.ends_with("\r\n\r\n0123456789abcdefghij"), "{}", get);
+    }
+
+    #[test]
+    fn test_head_should_not_send_chunked_body() {
+        let handler = |_req| {
+            Ok(Response::builder()
+                .chunks(vec![b"abc".to_vec(), b"de".to_vec()])
+                .into())
+        };
+        let server = testing::serve(HttpServer::builder(), handler);
+
+        let res =
+            server.exchange("HEAD / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert_eq!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: keep-alive\r\n\r\n\
+             HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+             3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n",
+            res
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_http_server {
+    use super::*;
+    use crate::http::testing;
+    use std::io::{BufReader, Write};
+
+    fn ok(_: Request) -> Result<Response, Error> {
+        Ok(Response::builder().body(b"ok".to_vec()).into())
+    }
+
+    #[test]
+    fn test_server_should_say_why_request_was_rejected() {
+        let server = testing::serve(HttpServer::builder(), ok);
+
+        let res = server.exchange("GET / HTTP/1.1\r\nNo colon\r\n\r\n");
+        assert!(res.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", res);
+        assert!(
+            res.contains("\r\n\r\n400 Bad Request: Invalid header field! (at byte "),
+            "{}",
+            res
+        );
+    }
+
+    #[test]
+    fn test_server_should_answer_errors_in_request_version() {
+        let handler = |req: Request| -> Result<Response, Error> {
+            match req.url.path.as_str() {
+                "/panic" => panic!("handler failed"),
+                _ => Err(Error::handler(Status::NotFound, "No such page!")),
+            }
+        };
+        let server = testing::serve(HttpServer::builder(), handler);
+
+        let vals = [
+            (
+                "GET /panic HTTP/1.0\r\n\r\n",
+                "HTTP/1.0 500 Internal Server Error\r\n",
+            ),
+            (
+                "GET /missing HTTP/1.0\r\n\r\n",
+                "HTTP/1.0 404 Not Found\r\n",
+            ),
+            (
+                "GET /missing HTTP/1.1\r\n\r\n",
+                "HTTP/1.1 404 Not Found\r\n",
+            ),
+        ];
+
+        for (raw, status_line) in vals.iter() {
+            let res = server.exchange(raw);
+            assert!(res.starts_with(status_line), "{}", res);
+        }
+    }
+
+    #[test]
+    fn test_server_should_answer_slow_request_head_with_408() {
+        let timeouts = Timeouts {
+            header: time::Duration::from_millis(200),
+            ..Timeouts::default()
+        };
+        let server = testing::serve(HttpServer::builder().timeouts(timeouts), ok);
+
+        let res = server.exchange("GET / HTTP/1.1\r\nHost: x\r\n");
+        assert!(
+            res.starts_with("HTTP/1.1 408 Request Timeout\r\n"),
+            "{}",
+            res
+        );
+        assert!(res.contains("Connection: close\r\n"), "{}", res);
+    }
+
+    #[test]
+    fn test_server_should_close_idle_keep_alive_connection_silently() {
+        let keep_alive = KeepAlive {
+            timeout: time::Duration::from_millis(200),
+            ..KeepAlive::default()
+        };
+        let server = testing::serve(HttpServer::builder().keep_alive(keep_alive), ok);
+
+        let mut reader = BufReader::new(server.connect());
+        reader
+            .get_mut()
+            .write_all(b"GET / HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let res = testing::read_response(&mut reader, false);
+        assert!(res.contains("Connection: keep-alive\r\n"), "{}", res);
+
+        assert_eq!("", testing::read_to_close(reader.get_mut()));
+    }
 }
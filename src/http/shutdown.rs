@@ -0,0 +1,244 @@
+use crate::net::socket::Socket;
+use nix::sys::socket::{shutdown, Shutdown as How};
+use std::{
+    collections::HashMap,
+    ops::Deref,
+    os::unix::io::{AsRawFd, RawFd},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Handle to stop an `HttpServer` from another thread or a signal handler.
+///
+/// Once triggered the server stops accepting, closes keep-alive connections
+/// waiting for their next request and lets `listen_and_serve` return after
+/// the requests in flight are done.
+#[derive(Clone)]
+pub struct Shutdown {
+    state: Arc<State>,
+}
+
+struct State {
+    requested: AtomicBool,
+    requested_at: Mutex<Option<Instant>>,
+    listeners: Vec<RawFd>,
+    /// Open connections, and whether each one is idle between requests.
+    connections: Mutex<HashMap<RawFd, bool>>,
+    drained: Condvar,
+}
+
+impl Shutdown {
+    pub(super) fn new(listeners: Vec<RawFd>) -> Self {
+        Self {
+            state: Arc::new(State {
+                requested: AtomicBool::new(false),
+                requested_at: Mutex::new(None),
+                listeners,
+                connections: Mutex::new(HashMap::new()),
+                drained: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Starts the shutdown and returns right away. Calling it again does
+    /// nothing.
+    pub fn shutdown(&self) {
+        if self.state.requested.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        *self.state.requested_at.lock().unwrap() = Some(Instant::now());
+        info!("Shutdown requested");
+
+        // Wakes up the blocked accept(2) calls.
+        for fd in self.state.listeners.iter() {
+            let _ = shutdown(*fd, How::Both);
+        }
+
+        let connections = self.state.connections.lock().unwrap();
+        for (fd, _) in connections.iter().filter(|(_, idle)| **idle) {
+            let _ = shutdown(*fd, How::Read);
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.state.requested.load(Ordering::SeqCst)
+    }
+
+    /// Starts tracking a freshly accepted connection. It counts as busy
+    /// until `TrackedSocket::set_idle` says otherwise.
+    pub(super) fn track(&self, socket: Socket) -> TrackedSocket {
+        let fd = socket.as_raw_fd();
+        self.state.connections.lock().unwrap().insert(fd, false);
+
+        TrackedSocket {
+            socket,
+            shutdown: self.clone(),
+        }
+    }
+
+    /// When connections still open `timeout` after the shutdown was
+    /// requested get cut off. `None` while no shutdown was requested.
+    pub(super) fn deadline(&self, timeout: Duration) -> Option<Instant> {
+        self.state
+            .requested_at
+            .lock()
+            .unwrap()
+            .map(|requested_at| requested_at + timeout)
+    }
+
+    /// Waits until `timeout` after the shutdown request for every tracked
+    /// connection to close, then cuts off the ones still open. Returns how
+    /// many had to be cut off.
+    pub(super) fn drain(&self, timeout: Duration) -> usize {
+        let deadline = self
+            .deadline(timeout)
+            .unwrap_or_else(|| Instant::now() + timeout);
+        let mut connections = self.state.connections.lock().unwrap();
+
+        while !connections.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            connections = self
+                .state
+                .drained
+                .wait_timeout(connections, deadline - now)
+                .unwrap()
+                .0;
+        }
+
+        for fd in connections.keys() {
+            let _ = shutdown(*fd, How::Both);
+        }
+        connections.len()
+    }
+}
+
+/// A connection known to `Shutdown`. It is forgotten before the socket is
+/// closed, so a reused descriptor is never shut down by mistake.
+pub(super) struct TrackedSocket {
+    socket: Socket,
+    shutdown: Shutdown,
+}
+
+impl TrackedSocket {
+    /// Marks the connection as waiting for its next request or as busy with
+    /// one. Returns `false` when it went idle during a shutdown and should be
+    /// closed.
+    pub(super) fn set_idle(&self, idle: bool) -> bool {
+        let mut connections = self.shutdown.state.connections.lock().unwrap();
+        connections.insert(self.socket.as_raw_fd(), idle);
+
+        !(idle && self.shutdown.is_requested())
+    }
+}
+
+impl Deref for TrackedSocket {
+    type Target = Socket;
+
+    fn deref(&self) -> &Socket {
+        &self.socket
+    }
+}
+
+impl Drop for TrackedSocket {
+    fn drop(&mut self) {
+        let mut connections = self.shutdown.state.connections.lock().unwrap();
+        connections.remove(&self.socket.as_raw_fd());
+        self.shutdown.state.drained.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test_shutdown {
+    use super::*;
+    use crate::http::{response::Response, server::HttpServer, testing};
+    use std::{
+        io::{BufReader, Read, Write},
+        net::{SocketAddr, TcpStream},
+        sync::mpsc,
+    };
+
+    /// A connected pair of the accepted server end and the client end.
+    fn connection() -> (Socket, TcpStream) {
+        let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let listener = Socket::new(&addr).unwrap();
+        listener.bind(&addr).unwrap();
+        listener.listen(1).unwrap();
+
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (listener.accept().unwrap(), client)
+    }
+
+    #[test]
+    fn test_set_idle_should_refuse_going_idle_during_shutdown() {
+        let shutdown = Shutdown::new(vec![]);
+        let (socket, _client) = connection();
+        let socket = shutdown.track(socket);
+
+        assert!(socket.set_idle(true));
+        assert!(socket.set_idle(false));
+        shutdown.shutdown();
+        assert!(socket.set_idle(false));
+        assert!(!socket.set_idle(true));
+
+        drop(socket);
+        assert_eq!(0, shutdown.drain(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_drain_should_cut_off_connections_after_deadline() {
+        let shutdown = Shutdown::new(vec![]);
+        assert_eq!(None, shutdown.deadline(Duration::from_secs(1)));
+
+        let (socket, mut client) = connection();
+        let _socket = shutdown.track(socket);
+        shutdown.shutdown();
+        assert!(shutdown.deadline(Duration::from_secs(1)).is_some());
+
+        let now = Instant::now();
+        assert_eq!(1, shutdown.drain(Duration::from_millis(100)));
+        assert!(now.elapsed() >= Duration::from_millis(90));
+
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(0, client.read(&mut [0; 16]).unwrap());
+    }
+
+    #[test]
+    fn test_shutdown_should_finish_requests_and_close_idle_connections() {
+        let (started, in_flight) = mpsc::channel();
+        let handler = move |req: crate::http::request::Request| {
+            if req.url.path == "/slow" {
+                started.send(()).unwrap();
+                std::thread::sleep(Duration::from_millis(300));
+            }
+            Ok(Response::builder().body(req.url.path.into_bytes()).into())
+        };
+        let server = testing::serve(HttpServer::builder().threads(2), handler);
+
+        let mut idle = server.connect();
+        idle.write_all(b"GET /fast HTTP/1.1\r\n\r\n").unwrap();
+        let mut idle = BufReader::new(idle);
+        let res = testing::read_response(&mut idle, false);
+        assert!(res.contains("Connection: keep-alive\r\n"), "{}", res);
+
+        let mut busy = server.connect();
+        busy.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+        in_flight.recv().unwrap();
+        server.shutdown.shutdown();
+
+        let res = testing::read_to_close(&mut busy);
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{}", res);
+        assert!(res.contains("Connection: close\r\n"), "{}", res);
+        assert!(res.ends_with("/slow"), "{}", res);
+
+        assert_eq!("", testing::read_to_close(idle.get_mut()));
+        server.stop().unwrap();
+    }
+}
@@ -1,10 +1,12 @@
-use std::string::ToString;
+use std::fmt;
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum Status {
     Continue,                      // 100 - RFC 7231, 6.2.1
     SwitchingProtocols,            // 101 - RFC 7231, 6.2.2
     Processing,                    // 102 - RFC 2518, 10.1
     EarlyHints,                    // 103 - RFC 8297
+    #[default]
     OK,                            // 200 - RFC 7231, 6.3.1
     Created,                       // 201 - RFC 7231, 6.3.2
     Accepted,                      // 202 - RFC 7231, 6.3.3
@@ -74,6 +76,13 @@ impl Status {
         self.get_code_and_string().1
     }
 
+    /// Informational, 204 and 304 responses never carry a body
+    /// (RFC 7230, 3.3.3).
+    pub fn has_body(&self) -> bool {
+        let code = self.get_code();
+        code >= 200 && code != 204 && code != 304
+    }
+
     pub fn get_code_and_string(&self) -> (u16, &'static str) {
         match self {
             Status::Continue => (100, "Continue"),
@@ -142,14 +151,8 @@ impl Status {
     }
 }
 
-impl ToString for Status {
-    fn to_string(&self) -> String {
-        self.get_code_and_string().1.to_string()
-    }
-}
-
-impl Default for Status {
-    fn default() -> Self {
-        Status::OK
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.get_string())
     }
 }
@@ -0,0 +1,112 @@
+use super::{builder::HttpServerBuilder, server::Handler, shutdown::Shutdown};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpStream},
+    thread,
+    time::Duration,
+};
+
+/// A server listening on a free loopback port on its own thread. It is shut
+/// down and joined when dropped.
+pub(super) struct TestServer {
+    pub(super) addr: SocketAddr,
+    pub(super) shutdown: Shutdown,
+    thread: Option<thread::JoinHandle<io::Result<()>>>,
+}
+
+/// Builds `builder` bound to `127.0.0.1:0` and serves `handler` with it.
+pub(super) fn serve(builder: HttpServerBuilder, handler: impl Handler) -> TestServer {
+    let server = builder.bind("127.0.0.1:0").build().unwrap();
+    let addr = server.local_addrs().unwrap()[0];
+    let shutdown = server.shutdown_handle();
+    let thread = thread::spawn(move || server.listen_and_serve(handler));
+
+    TestServer {
+        addr,
+        shutdown,
+        thread: Some(thread),
+    }
+}
+
+impl TestServer {
+    /// Connects to the server, retrying until `listen_and_serve` listens.
+    pub(super) fn connect(&self) -> TcpStream {
+        for _ in 0..100 {
+            match TcpStream::connect(self.addr) {
+                Ok(stream) => {
+                    stream
+                        .set_read_timeout(Some(Duration::from_secs(5)))
+                        .unwrap();
+                    return stream;
+                }
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => panic!("Can't connect to {}: {}", self.addr, e),
+            }
+        }
+        panic!("Server on {} never started listening!", self.addr);
+    }
+
+    /// Sends `raw` on a new connection and returns everything the server
+    /// answers until it closes the connection.
+    pub(super) fn exchange(&self, raw: &str) -> String {
+        let mut stream = self.connect();
+        stream.write_all(raw.as_bytes()).unwrap();
+        read_to_close(&mut stream)
+    }
+
+    /// Shuts the server down and returns what `listen_and_serve` returned.
+    pub(super) fn stop(mut self) -> io::Result<()> {
+        self.shutdown.shutdown();
+        self.thread.take().unwrap().join().unwrap()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.shutdown.shutdown();
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Reads until the server closes the connection.
+pub(super) fn read_to_close(stream: &mut TcpStream) -> String {
+    let mut res = vec![];
+    stream.read_to_end(&mut res).unwrap();
+    String::from_utf8_lossy(&res).into_owned()
+}
+
+/// Reads one response whose body is `Content-Length` bytes long, or empty
+/// when `head_only` is set as for a HEAD request.
+pub(super) fn read_response(reader: &mut BufReader<TcpStream>, head_only: bool) -> String {
+    let mut res = String::new();
+    let mut length = 0;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap() == 0 {
+            panic!("Connection closed in the middle of {:?}", res);
+        }
+        res.push_str(&line);
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, val)) = line.split_once(':') {
+            if key.eq_ignore_ascii_case("Content-Length") {
+                length = val.trim().parse().unwrap();
+            }
+        }
+    }
+
+    if !head_only {
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        res.push_str(&String::from_utf8_lossy(&body));
+    }
+    res
+}
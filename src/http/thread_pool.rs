@@ -1,94 +1,505 @@
 use std::{
+    collections::HashMap,
     io,
-    sync::{mpsc, Arc, Mutex},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, PoisonError,
+    },
     thread,
+    time::{Duration, Instant},
 };
 
+/// How many workers a `ThreadPool` runs and how many jobs it queues.
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    /// Workers kept around even when idle. At least one, so a queued job
+    /// always has a worker left to pick it up.
+    pub min_workers: usize,
+    /// Most workers started while jobs are waiting.
+    pub max_workers: usize,
+    /// How long a worker above `min_workers` may sit idle before it stops.
+    pub idle_timeout: Duration,
+    /// Jobs that may wait once `max_workers` are busy.
+    pub queue_capacity: usize,
+    /// Prefix of the worker threads' names, followed by their id.
+    pub thread_name: String,
+    /// Stack size of the worker threads, the standard library's default if
+    /// `None`.
+    pub stack_size: Option<usize>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            min_workers: 4,
+            max_workers: 32,
+            idle_timeout: Duration::from_secs(60),
+            queue_capacity: 256,
+            thread_name: String::from("http-worker"),
+            stack_size: None,
+        }
+    }
+}
+
+impl PoolConfig {
+    pub(super) fn validate(&self) -> io::Result<()> {
+        let problem = if self.max_workers == 0 {
+            "max_workers has to be at least 1"
+        } else if self.min_workers == 0 {
+            "min_workers has to be at least 1"
+        } else if self.min_workers > self.max_workers {
+            "min_workers can't be more than max_workers"
+        } else if self.queue_capacity == 0 {
+            "queue_capacity has to be at least 1"
+        } else if self.idle_timeout.is_zero() {
+            "idle_timeout can't be zero"
+        } else {
+            return Ok(());
+        };
+
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid pool config: {}!", problem),
+        ))
+    }
+}
+
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: mpsc::Sender<Message>,
+    sender: Option<mpsc::SyncSender<Job>>,
+    shared: Arc<Shared>,
 }
 
-enum Message {
-    NewJob(Job),
-    Terminate,
+/// State every worker has access to.
+struct Shared {
+    config: PoolConfig,
+    receiver: Mutex<mpsc::Receiver<Job>>,
+    /// Jobs queued or running.
+    jobs: AtomicUsize,
+    /// Workers currently running.
+    workers: AtomicUsize,
+    /// Jobs that panicked, plus workers that died outside of a job.
+    panics: AtomicUsize,
+    next_id: AtomicUsize,
+    threads: Mutex<HashMap<usize, thread::JoinHandle<()>>>,
 }
 
-type Job = Box<dyn FnOnce() -> io::Result<()> + Send + 'static>;
+pub(super) type Job = Box<dyn FnOnce() -> io::Result<()> + Send + 'static>;
 
 impl ThreadPool {
+    /// A pool of always `size` workers, with room for as many waiting jobs
+    /// as `PoolConfig` allows by default.
     pub fn new(size: usize) -> Self {
+        Self::with_capacity(size, PoolConfig::default().queue_capacity)
+    }
+
+    /// A pool of always `size` workers, with room for `capacity` jobs
+    /// waiting for one of them.
+    pub fn with_capacity(size: usize, capacity: usize) -> Self {
         assert!(size > 0);
+        assert!(capacity > 0);
 
-        let (sender, receiver) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(receiver));
+        let config = PoolConfig {
+            min_workers: size,
+            max_workers: size,
+            queue_capacity: capacity,
+            ..PoolConfig::default()
+        };
+        ThreadPool::with_config(config).expect("Error starting workers")
+    }
+
+    /// A pool that starts `config.min_workers` workers and adds more, up to
+    /// `config.max_workers`, while jobs are waiting.
+    pub fn with_config(config: PoolConfig) -> io::Result<Self> {
+        config.validate()?;
+
+        let (sender, receiver) = mpsc::sync_channel(config.queue_capacity);
+        let shared = Arc::new(Shared {
+            receiver: Mutex::new(receiver),
+            jobs: AtomicUsize::new(0),
+            workers: AtomicUsize::new(0),
+            panics: AtomicUsize::new(0),
+            next_id: AtomicUsize::new(0),
+            threads: Mutex::new(HashMap::new()),
+            config,
+        });
 
-        let mut workers = Vec::with_capacity(size);
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+        for _ in 0..shared.config.min_workers {
+            shared.workers.fetch_add(1, Ordering::SeqCst);
+            Worker::spawn(&shared)?;
         }
 
-        Self { workers, sender }
+        Ok(Self {
+            sender: Some(sender),
+            shared,
+        })
     }
 
-    pub fn execute<F>(&self, f: F) -> ()
+    /// Queues `f` for the next free worker, starting a new one if all are
+    /// busy. Blocks while the queue is full.
+    pub fn execute<F>(&self, f: F)
     where
         F: FnOnce() -> io::Result<()> + Send + 'static,
     {
-        let job = Box::new(f);
+        self.count_job();
         self.sender
-            .send(Message::NewJob(job))
+            .as_ref()
+            .unwrap()
+            .send(Box::new(f))
             .expect("Error sending new job message");
     }
+
+    /// Like `execute`, but hands `job` back instead of waiting while the
+    /// queue is full. That includes the moment before an idle worker picked
+    /// up what was queued, even if `is_full` says there is room.
+    pub(super) fn try_execute(&self, job: Job) -> Result<(), Job> {
+        self.count_job();
+        match self.sender.as_ref().unwrap().try_send(job) {
+            Ok(()) => Ok(()),
+            Err(mpsc::TrySendError::Full(job)) => {
+                self.shared.jobs.fetch_sub(1, Ordering::SeqCst);
+                Err(job)
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => panic!("Error sending new job message"),
+        }
+    }
+
+    /// Counts a new job, starting a new worker if all are busy.
+    fn count_job(&self) {
+        let jobs = self.shared.jobs.fetch_add(1, Ordering::SeqCst) + 1;
+        if jobs > self.size() {
+            self.grow();
+        }
+    }
+
+    /// Starts one more worker unless there are `max_workers` already.
+    fn grow(&self) {
+        let max_workers = self.shared.config.max_workers;
+        let grown =
+            self.shared
+                .workers
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |workers| {
+                    Some(workers + 1).filter(|workers| *workers <= max_workers)
+                });
+
+        if grown.is_ok() {
+            if let Err(e) = Worker::spawn(&self.shared) {
+                self.shared.workers.fetch_sub(1, Ordering::SeqCst);
+                error!("Couldn't start a new worker: {}", e);
+            }
+        }
+    }
+
+    /// How many workers are running.
+    pub fn size(&self) -> usize {
+        self.shared.workers.load(Ordering::SeqCst)
+    }
+
+    /// How many jobs are waiting for a worker.
+    pub fn queue_len(&self) -> usize {
+        let jobs = self.shared.jobs.load(Ordering::SeqCst);
+        jobs.saturating_sub(self.size())
+    }
+
+    /// Whether `max_workers` are busy and the queue has no room left.
+    pub fn is_full(&self) -> bool {
+        let config = &self.shared.config;
+        self.shared.jobs.load(Ordering::SeqCst) >= config.max_workers + config.queue_capacity
+    }
+
+    /// How many jobs panicked so far.
+    pub fn panic_count(&self) -> usize {
+        self.shared.panics.load(Ordering::SeqCst)
+    }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        debug!("Sending terminate message to all workers.");
-        for _ in &self.workers {
-            self.sender
-                .send(Message::Terminate)
-                .expect("Error sending terminate message");
-        }
+        debug!("Telling all workers to terminate.");
+        drop(self.sender.take());
 
         debug!("Shutting down all workers.");
-        for worker in &mut self.workers {
-            debug!("Shutting down worker {}", worker.id);
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+        // A worker that died is replaced, so join until no thread is left.
+        loop {
+            let threads: Vec<_> = self.shared.lock_threads().drain().collect();
+            if threads.is_empty() {
+                break;
+            }
+
+            for (id, thread) in threads {
+                debug!("Shutting down worker {}", id);
+                if thread.join().is_err() {
+                    error!("Worker {} panicked while shutting down", id);
+                }
             }
         }
     }
 }
 
-pub struct Worker {
-    id: usize,
-    thread: Option<thread::JoinHandle<()>>,
+impl Shared {
+    fn lock_threads(&self) -> std::sync::MutexGuard<'_, HashMap<usize, thread::JoinHandle<()>>> {
+        self.threads.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Takes a worker out of the count unless that would leave fewer than
+    /// `min_workers`.
+    fn retire(&self) -> bool {
+        let min_workers = self.config.min_workers;
+        self.workers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |workers| {
+                Some(workers - 1).filter(|workers| *workers >= min_workers)
+            })
+            .is_ok()
+    }
 }
 
+struct Worker;
+
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Self {
-        let thread = thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv().unwrap();
-
-            match message {
-                Message::NewJob(job) => {
-                    debug!("Worker {} got a job; executing.", id);
-                    match job() {
-                        Err(e) => error!("Worker {} got an error {}", id, e),
-                        Ok(()) => debug!("Worker {} finsihed job successfully", id),
+    /// Starts a worker thread. It has to be counted in `shared.workers`
+    /// already.
+    fn spawn(shared: &Arc<Shared>) -> io::Result<()> {
+        let id = shared.next_id.fetch_add(1, Ordering::SeqCst);
+        let mut builder =
+            thread::Builder::new().name(format!("{}-{}", shared.config.thread_name, id));
+        if let Some(stack_size) = shared.config.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+
+        // Held until the handle is stored, so a worker stopping right away
+        // can't try to forget it before that.
+        let mut threads = shared.lock_threads();
+        let sentinel = Sentinel {
+            id,
+            shared: Arc::clone(shared),
+        };
+        let thread = builder.spawn(move || {
+            Worker::run(id, &sentinel.shared);
+            drop(sentinel);
+        })?;
+
+        threads.insert(id, thread);
+        Ok(())
+    }
+
+    fn run(id: usize, shared: &Shared) {
+        let idle_timeout = shared.config.idle_timeout;
+        let mut idle_since = Instant::now();
+
+        loop {
+            let job = {
+                let receiver = shared
+                    .receiver
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+
+                if idle_since.elapsed() >= idle_timeout {
+                    if shared.retire() {
+                        debug!("Worker {} was idle for too long; stopping.", id);
+                        // Nobody is going to join this thread any more.
+                        shared.lock_threads().remove(&id);
+                        return;
                     }
+                    idle_since = Instant::now();
                 }
-                Message::Terminate => {
+                receiver.recv_timeout(idle_timeout - idle_since.elapsed().min(idle_timeout))
+            };
+
+            let job = match job {
+                Ok(job) => job,
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
                     debug!("Worker {} was told to terminate.", id);
-                    break;
+                    shared.workers.fetch_sub(1, Ordering::SeqCst);
+                    return;
+                }
+            };
+
+            debug!("Worker {} got a job; executing.", id);
+            let result = panic::catch_unwind(AssertUnwindSafe(job));
+            shared.jobs.fetch_sub(1, Ordering::SeqCst);
+            idle_since = Instant::now();
+
+            match result {
+                Ok(Err(e)) => error!("Worker {} got an error {}", id, e),
+                Ok(Ok(())) => debug!("Worker {} finsihed job successfully", id),
+                Err(_) => {
+                    let panics = shared.panics.fetch_add(1, Ordering::SeqCst) + 1;
+                    error!("Worker {} caught a panicking job ({} so far)", id, panics);
                 }
             }
+        }
+    }
+}
+
+/// Replaces a worker whose thread dies of a panic outside of a job.
+struct Sentinel {
+    id: usize,
+    shared: Arc<Shared>,
+}
+
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            return;
+        }
+
+        self.shared.panics.fetch_add(1, Ordering::SeqCst);
+        self.shared.lock_threads().remove(&self.id);
+        error!("Worker {} died, starting a new one", self.id);
+
+        if let Err(e) = Worker::spawn(&self.shared) {
+            self.shared.workers.fetch_sub(1, Ordering::SeqCst);
+            error!("Couldn't start a new worker: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_thread_pool {
+    use super::*;
+    use std::sync::mpsc::{channel, Receiver, Sender};
+
+    #[test]
+    fn test_thread_pool_should_be_full_once_workers_and_queue_are_taken() {
+        let pool = ThreadPool::with_capacity(2, 2);
+        let (release, released) = channel::<()>();
+        let released = Arc::new(Mutex::new(released));
+
+        for expected_queue_len in [0, 0, 1, 2].iter() {
+            let released = Arc::clone(&released);
+            pool.execute(move || {
+                released.lock().unwrap().recv().unwrap();
+                Ok(())
+            });
+            assert_eq!(*expected_queue_len, pool.queue_len());
+        }
+        assert!(pool.is_full());
+
+        for _ in 0..4 {
+            release.send(()).unwrap();
+        }
+        drop(pool);
+    }
+
+    #[test]
+    fn test_try_execute_should_hand_job_back_when_full() {
+        let pool = ThreadPool::with_capacity(1, 1);
+        let (started, running) = channel::<()>();
+        let (release, released) = channel::<()>();
+        let released = Arc::new(Mutex::new(released));
+
+        let job = |started: Sender<()>, released: Arc<Mutex<Receiver<()>>>| -> Job {
+            Box::new(move || {
+                started.send(()).unwrap();
+                released.lock().unwrap().recv().unwrap();
+                Ok(())
+            })
+        };
+        assert!(pool
+            .try_execute(job(started.clone(), Arc::clone(&released)))
+            .is_ok());
+        running.recv().unwrap();
+        assert!(pool
+            .try_execute(job(started, Arc::clone(&released)))
+            .is_ok());
+        assert!(pool.is_full());
+
+        assert!(pool.try_execute(Box::new(|| Ok(()))).is_err());
+        assert_eq!(1, pool.queue_len());
+
+        for _ in 0..2 {
+            release.send(()).unwrap();
+        }
+        drop(pool);
+    }
+
+    /// Panics again when dropped, which kills the worker dropping it.
+    struct Bomb;
+
+    impl Drop for Bomb {
+        fn drop(&mut self) {
+            panic!("bomb dropped");
+        }
+    }
+
+    #[test]
+    fn test_thread_pool_should_survive_panics() {
+        let pool = ThreadPool::new(1);
+        pool.execute(|| panic!("job failed"));
+        pool.execute(|| panic::panic_any(Bomb));
+
+        let (done, finished) = channel();
+        pool.execute(move || {
+            done.send(()).unwrap();
+            Ok(())
         });
 
-        Self {
-            id,
-            thread: Some(thread),
+        finished.recv().unwrap();
+        assert_eq!(3, pool.panic_count());
+        drop(pool);
+    }
+
+    #[test]
+    fn test_thread_pool_should_grow_while_busy_and_shrink_when_idle() {
+        let pool = ThreadPool::with_config(PoolConfig {
+            min_workers: 1,
+            max_workers: 3,
+            idle_timeout: Duration::from_millis(50),
+            ..PoolConfig::default()
+        })
+        .unwrap();
+        assert_eq!(1, pool.size());
+
+        let (release, released) = channel::<()>();
+        let released = Arc::new(Mutex::new(released));
+        for _ in 0..4 {
+            let released = Arc::clone(&released);
+            pool.execute(move || {
+                released.lock().unwrap().recv().unwrap();
+                Ok(())
+            });
+        }
+        assert_eq!(3, pool.size());
+        assert_eq!(1, pool.queue_len());
+
+        for _ in 0..4 {
+            release.send(()).unwrap();
+        }
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.size() > 1 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(1, pool.size());
+        assert_eq!(0, pool.queue_len());
+    }
+
+    #[test]
+    fn test_thread_pool_should_reject_invalid_config() {
+        let configs = [
+            PoolConfig {
+                max_workers: 0,
+                min_workers: 0,
+                ..PoolConfig::default()
+            },
+            PoolConfig {
+                min_workers: 0,
+                ..PoolConfig::default()
+            },
+            PoolConfig {
+                min_workers: 5,
+                max_workers: 4,
+                ..PoolConfig::default()
+            },
+            PoolConfig {
+                queue_capacity: 0,
+                ..PoolConfig::default()
+            },
+        ];
+
+        for config in configs.iter() {
+            let err = ThreadPool::with_config(config.clone()).err().unwrap();
+            assert_eq!(io::ErrorKind::InvalidInput, err.kind());
         }
     }
 }
@@ -6,12 +6,14 @@ use std::{
 #[derive(Debug)]
 pub struct URL {
     pub path: String,
+    pub query: Option<String>,
 }
 
 impl URL {
     pub fn new(path: &str) -> Self {
         Self {
             path: path.to_string(),
+            query: None,
         }
     }
 }
@@ -27,9 +29,41 @@ impl FromStr for URL {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if !s.starts_with("/") {
-            Err(Error::new(ErrorKind::InvalidInput, "Invalid url!"))
+            return Err(Error::new(ErrorKind::InvalidInput, "Invalid url!"));
+        }
+
+        let s = s.split('#').next().unwrap_or(s);
+        let (path, query) = match s.find('?') {
+            Some(i) => (&s[..i], Some(s[i + 1..].to_string())),
+            None => (s, None),
+        };
+
+        Ok(URL {
+            path: path.to_string(),
+            query,
+        })
+    }
+}
+
+/// Decodes `%XX` escapes. Returns `None` on malformed escapes.
+pub fn percent_decode(s: &str) -> Option<Vec<u8>> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
         } else {
-            Ok(URL::new(s))
+            decoded.push(bytes[i]);
+            i += 1;
         }
     }
+
+    Some(decoded)
 }
@@ -1,34 +1,36 @@
 use std::{
+    fmt,
     io::{Error, ErrorKind},
     str::FromStr,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum Version {
+    V1P0,
+    #[default]
     V1P1,
 }
 
-impl Default for Version {
-    fn default() -> Self {
-        Version::V1P1
-    }
-}
-
 impl FromStr for Version {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
+            "HTTP/1.0" => Ok(Version::V1P0),
             "HTTP/1.1" => Ok(Version::V1P1),
-            _ => Err(Error::new(ErrorKind::InvalidInput, "HTTP version not supported!")),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "HTTP version not supported!",
+            )),
         }
     }
 }
 
-impl ToString for Version {
-    fn to_string(&self) -> String {
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Version::V1P1 => String::from("HTTP/1.1"),
+            Version::V1P0 => write!(f, "HTTP/1.0"),
+            Version::V1P1 => write!(f, "HTTP/1.1"),
         }
     }
 }
@@ -1,12 +1,17 @@
-use server_from_scratch::http::{file_server, server::HttpServer};
+use server_from_scratch::http::{
+    file_server,
+    middleware::{HandlerExt, Logger},
+    server::HttpServer,
+};
 use std::io;
 
 fn main() -> io::Result<()> {
     pretty_env_logger::init();
 
     let server = HttpServer::new(8080)?;
+    server.shutdown_on_signal()?;
     let handler = file_server::FileServer::new("/Users/doom/Downloads");
-    server.listen_and_serve(handler)?;
+    server.listen_and_serve(handler.with(Logger))?;
 
     Ok(())
 }
@@ -1 +1,3 @@
-pub mod socket;
\ No newline at end of file
+pub mod socket;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
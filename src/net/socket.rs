@@ -1,21 +1,80 @@
 use nix::{
+    errno::Errno,
+    fcntl::{fcntl, FcntlArg, OFlag},
+    libc,
     sys::socket::{
-        accept, bind, listen, recvfrom, send, setsockopt, socket, sockopt::ReuseAddr,
-        AddressFamily, InetAddr, IpAddr, MsgFlags, SockAddr, SockFlag, SockProtocol, SockType,
+        accept, bind, getsockname, listen, recvfrom, send, setsockopt, socket,
+        sockopt::{self, ReceiveTimeout, ReuseAddr, SendTimeout, TcpNoDelay},
+        AddressFamily, InetAddr, MsgFlags, SetSockOpt, SockAddr, SockFlag, SockProtocol, SockType,
     },
+    sys::time::{TimeVal, TimeValLike},
     unistd::close,
 };
 
-use std::io;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+use super::uring;
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    mem,
+    net::SocketAddr,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::FileTypeExt,
+        io::{AsRawFd, RawFd},
+    },
+    path::Path,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Most file bytes the io_uring backend sends per `send_file` call.
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+const SEND_FILE_CHUNK_SIZE: usize = 64 * 1024;
 
 pub struct Socket {
     fd: i32,
+    family: AddressFamily,
+    backend: IoBackend,
+    /// Read and write timeouts in microseconds, 0 for none. The io_uring
+    /// backend enforces them itself, as `SO_RCVTIMEO` and `SO_SNDTIMEO`
+    /// only apply to system calls.
+    read_timeout: AtomicU64,
+    write_timeout: AtomicU64,
+    /// io_uring waits for readiness even on non-blocking sockets, so those
+    /// always go through plain system calls.
+    nonblocking: AtomicBool,
+}
+
+/// How a socket carries out accept, receive, send and file reads.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IoBackend {
+    /// One blocking system call per operation.
+    #[default]
+    Syscalls,
+    /// Operations are submitted to a per-thread io_uring.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    IoUring,
+}
+
+/// Identity of the process on the other end of a Unix domain socket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeerCredentials {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
 }
 
 impl Socket {
-    pub fn new() -> io::Result<Self> {
+    /// Creates a TCP socket of the address family `addr` belongs to.
+    pub fn new(addr: &SocketAddr) -> io::Result<Self> {
+        let family = match addr {
+            SocketAddr::V4(_) => AddressFamily::Inet,
+            SocketAddr::V6(_) => AddressFamily::Inet6,
+        };
         let fd = socket(
-            AddressFamily::Inet,
+            family,
             SockType::Stream,
             SockFlag::empty(),
             SockProtocol::Tcp,
@@ -25,51 +84,285 @@ impl Socket {
         setsockopt(fd, ReuseAddr, &true)
             .map_err(|err| nix_to_io_error(err, "Socket Allocation Options Error!"))?;
         debug!("New Socket {} created.", fd);
-        Ok(Socket { fd })
+        Ok(Socket::from_fd(fd, family, IoBackend::default()))
+    }
+
+    /// Creates a Unix domain stream socket.
+    pub fn new_unix() -> io::Result<Self> {
+        let fd = socket(
+            AddressFamily::Unix,
+            SockType::Stream,
+            SockFlag::empty(),
+            None,
+        )
+        .map_err(|err| nix_to_io_error(err, "Socket Allocation Error!"))?;
+
+        debug!("New Unix Socket {} created.", fd);
+        Ok(Socket::from_fd(
+            fd,
+            AddressFamily::Unix,
+            IoBackend::default(),
+        ))
     }
 
-    pub fn bind(&self, port: u16) -> io::Result<()> {
-        let ip_addr = IpAddr::new_v4(127, 0, 0, 1);
-        let inet_addr = InetAddr::new(ip_addr, port);
-        let socket_addr = &SockAddr::new_inet(inet_addr);
+    fn from_fd(fd: i32, family: AddressFamily, backend: IoBackend) -> Self {
+        Socket {
+            fd,
+            family,
+            backend,
+            read_timeout: AtomicU64::new(0),
+            write_timeout: AtomicU64::new(0),
+            nonblocking: AtomicBool::new(false),
+        }
+    }
+
+    /// Switches the backend used by this socket and the ones it accepts.
+    /// Fails if the backend isn't usable on this machine.
+    pub fn set_backend(&mut self, backend: IoBackend) -> io::Result<()> {
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if backend == IoBackend::IoUring {
+            uring::probe()?;
+        }
+
+        self.backend = backend;
+        Ok(())
+    }
+
+    pub fn backend(&self) -> IoBackend {
+        self.backend
+    }
+
+    /// The backend the next call goes through.
+    fn current_backend(&self) -> IoBackend {
+        if self.nonblocking.load(Ordering::Relaxed) {
+            return IoBackend::Syscalls;
+        }
+        self.backend
+    }
+
+    pub fn bind(&self, addr: &SocketAddr) -> io::Result<()> {
+        let socket_addr = &SockAddr::new_inet(InetAddr::from_std(addr));
 
         bind(self.fd, socket_addr).map_err(|err| nix_to_io_error(err, "Socket Bind Error!"))?;
-        debug!("Socket {} bound.", self.fd);
+        debug!("Socket {} bound to {}.", self.fd, addr);
+        Ok(())
+    }
+
+    /// Binds a Unix domain socket to a file system path.
+    pub fn bind_unix(&self, path: &Path) -> io::Result<()> {
+        let unix_addr = UnixSocketAddr::new(path.as_os_str().as_bytes(), false)?;
+
+        unix_addr
+            .bind(self.fd)
+            .map_err(|err| nix_to_io_error(err, "Socket Bind Error!"))?;
+        debug!("Socket {} bound to {:?}.", self.fd, path);
         Ok(())
     }
 
+    /// Binds a Unix domain socket to a name in the abstract namespace. Such
+    /// sockets have no file and vanish once closed.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub fn bind_abstract(&self, name: &[u8]) -> io::Result<()> {
+        let unix_addr = UnixSocketAddr::new(name, true)?;
+
+        unix_addr
+            .bind(self.fd)
+            .map_err(|err| nix_to_io_error(err, "Socket Bind Error!"))?;
+        debug!("Socket {} bound to abstract name {:?}.", self.fd, name);
+        Ok(())
+    }
+
+    /// Restricts an IPv6 socket to IPv6 peers. Without it a socket bound to
+    /// `::` also accepts IPv4 clients, as IPv4-mapped addresses. Has to be
+    /// set before `bind`.
+    pub fn set_only_v6(&self, only_v6: bool) -> io::Result<()> {
+        setsockopt(self.fd, Ipv6V6Only, &only_v6)
+            .map_err(|err| nix_to_io_error(err, "Socket IPv6 Only Error!"))?;
+        Ok(())
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match getsockname(self.fd) {
+            Ok(SockAddr::Inet(inet_addr)) => Ok(inet_addr.to_std()),
+            Ok(_) => Err(io::Error::other("Socket isn't an internet socket!")),
+            Err(err) => Err(nix_to_io_error(err, "Socket Name Error!")),
+        }
+    }
+
     pub fn listen(&self, backlog: usize) -> io::Result<()> {
         listen(self.fd, backlog).map_err(|err| nix_to_io_error(err, "Socket Listen Error!"))?;
         debug!("Socket {} listening.", self.fd);
         Ok(())
     }
 
+    /// Accepts a connection, which uses the same backend as the listener.
     pub fn accept(&self) -> io::Result<Self> {
-        let fd = accept(self.fd).map_err(|err| nix_to_io_error(err, "Socket Accept Error!"))?;
+        let fd = match self.current_backend() {
+            IoBackend::Syscalls => {
+                accept(self.fd).map_err(|err| nix_to_io_error(err, "Socket Accept Error!"))?
+            }
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            IoBackend::IoUring => uring::accept(self.fd)?,
+        };
         debug!("Socket {} accepted new Socket {}.", self.fd, fd);
-        Ok(Socket { fd })
+        Ok(Socket::from_fd(fd, self.family, self.backend))
     }
 
     pub fn receive(&self, buf: &mut [u8]) -> io::Result<usize> {
-        let (read_bytes, _) =
-            recvfrom(self.fd, buf).map_err(|err| nix_to_io_error(err, "Socket Receive Error!"))?;
+        let read_bytes = match self.current_backend() {
+            IoBackend::Syscalls => {
+                recvfrom(self.fd, buf)
+                    .map_err(|err| nix_to_io_error(err, "Socket Receive Error!"))?
+                    .0
+            }
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            IoBackend::IoUring => uring::receive(self.fd, buf, self.read_timeout())?,
+        };
         debug!("Reading from socket {}.", self.fd);
         Ok(read_bytes)
     }
 
     pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
-        let sent_bytes = send(self.fd, buf, MsgFlags::empty())
-            .map_err(|err| nix_to_io_error(err, "Socket Send Error!"))?;
+        let sent_bytes = match self.current_backend() {
+            IoBackend::Syscalls => send(self.fd, buf, MsgFlags::empty())
+                .map_err(|err| nix_to_io_error(err, "Socket Send Error!"))?,
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            IoBackend::IoUring => uring::send(self.fd, buf, self.write_timeout())?,
+        };
         debug!("Sending to socket {}.", self.fd);
         Ok(sent_bytes)
     }
 
+    pub fn send_all(&self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let sent_bytes = self.send(buf)?;
+            buf = &buf[sent_bytes..];
+        }
+        Ok(())
+    }
+
+    /// Sends up to `count` bytes of `file` starting at `offset` without
+    /// copying them through user space. Advances `offset` past the sent bytes.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub fn send_file(&self, file: &File, offset: &mut u64, count: usize) -> io::Result<usize> {
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if self.current_backend() == IoBackend::IoUring {
+            return self.send_file_through_ring(file, offset, count);
+        }
+
+        let mut off = *offset as i64;
+        let sent_bytes =
+            nix::sys::sendfile::sendfile(self.fd, file.as_raw_fd(), Some(&mut off), count)
+                .map_err(|err| nix_to_io_error(err, "Socket Send File Error!"))?;
+        *offset = off as u64;
+        debug!("Sending file to socket {}.", self.fd);
+        Ok(sent_bytes)
+    }
+
+    /// io_uring has no sendfile, so the file is read into a buffer and sent
+    /// from there, one chunk per call.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    fn send_file_through_ring(
+        &self,
+        file: &File,
+        offset: &mut u64,
+        count: usize,
+    ) -> io::Result<usize> {
+        let buf = &mut [0; SEND_FILE_CHUNK_SIZE];
+        let len = count.min(buf.len());
+
+        let read_bytes = uring::read_at(file.as_raw_fd(), &mut buf[..len], *offset)?;
+        self.send_all(&buf[..read_bytes])?;
+        *offset += read_bytes as u64;
+        debug!("Sending file to socket {}.", self.fd);
+        Ok(read_bytes)
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    pub fn send_file(&self, _file: &File, _offset: &mut u64, _count: usize) -> io::Result<usize> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+
+    pub fn is_unix(&self) -> bool {
+        self.family == AddressFamily::Unix
+    }
+
+    /// Credentials of the peer process, as recorded when it connected.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub fn peer_credentials(&self) -> io::Result<PeerCredentials> {
+        let credentials = nix::sys::socket::getsockopt(self.fd, sockopt::PeerCredentials)
+            .map_err(|err| nix_to_io_error(err, "Socket Peer Credentials Error!"))?;
+
+        Ok(PeerCredentials {
+            pid: credentials.pid(),
+            uid: credentials.uid(),
+            gid: credentials.gid(),
+        })
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    pub fn peer_credentials(&self) -> io::Result<PeerCredentials> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+
+    /// Disables Nagle's algorithm so a response split over several sends
+    /// doesn't wait on the client's delayed ACK.
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        setsockopt(self.fd, TcpNoDelay, &nodelay)
+            .map_err(|err| nix_to_io_error(err, "Socket No Delay Error!"))?;
+        Ok(())
+    }
+
+    /// Makes every call return `io::ErrorKind::WouldBlock` instead of
+    /// waiting when it can't make progress.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        let flags = fcntl(self.fd, FcntlArg::F_GETFL)
+            .map_err(|err| nix_to_io_error(err, "Socket Flags Error!"))?;
+        let mut flags = OFlag::from_bits_truncate(flags);
+        flags.set(OFlag::O_NONBLOCK, nonblocking);
+
+        fcntl(self.fd, FcntlArg::F_SETFL(flags))
+            .map_err(|err| nix_to_io_error(err, "Socket Flags Error!"))?;
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Makes `receive` fail with `io::ErrorKind::WouldBlock` once no data
+    /// arrived for `timeout`. `None` blocks forever.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        setsockopt(self.fd, ReceiveTimeout, &to_time_val(timeout))
+            .map_err(|err| nix_to_io_error(err, "Socket Receive Timeout Error!"))?;
+        self.read_timeout
+            .store(to_micros(timeout), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Makes `send` fail with `io::ErrorKind::WouldBlock` once the client
+    /// took no data for `timeout`. `None` blocks forever.
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        setsockopt(self.fd, SendTimeout, &to_time_val(timeout))
+            .map_err(|err| nix_to_io_error(err, "Socket Send Timeout Error!"))?;
+        self.write_timeout
+            .store(to_micros(timeout), Ordering::Relaxed);
+        Ok(())
+    }
+
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    fn read_timeout(&self) -> Option<Duration> {
+        from_micros(self.read_timeout.load(Ordering::Relaxed))
+    }
+
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    fn write_timeout(&self) -> Option<Duration> {
+        from_micros(self.write_timeout.load(Ordering::Relaxed))
+    }
+
     pub fn shutdown(&self) -> io::Result<()> {
         close(self.fd).map_err(|err| nix_to_io_error(err, "Socket Shutdown Error!"))?;
         Ok(())
     }
 
-    pub fn incoming(&self) -> Connections {
+    pub fn incoming(&self) -> Connections<'_> {
         Connections::new(self)
     }
 }
@@ -83,6 +376,28 @@ impl Drop for Socket {
     }
 }
 
+impl AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Read for &Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.receive(buf)
+    }
+}
+
+impl Write for &Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 pub struct Connections<'a> {
     listener: &'a Socket,
 }
@@ -93,17 +408,287 @@ impl<'a> Connections<'a> {
     }
 }
 
+/// Never ends on its own. Failed accepts are handed to the caller, which
+/// decides whether to keep going.
 impl<'a> Iterator for Connections<'a> {
-    type Item = Socket;
+    type Item = io::Result<Socket>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.listener.accept().ok()
+        Some(self.listener.accept())
+    }
+}
+
+/// Removes the socket file at `path` if no server is listening on it any
+/// more. Anything that isn't a socket, or is still in use, is left alone.
+pub fn remove_stale_unix_socket(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {}
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{:?} exists and isn't a socket!", path),
+            ))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    }
+
+    let probe = Socket::new_unix()?;
+    let unix_addr = UnixSocketAddr::new(path.as_os_str().as_bytes(), false)?;
+
+    match unix_addr.connect(probe.fd) {
+        Ok(()) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{:?} is still in use!", path),
+        )),
+        Err(err) if err.as_errno() == Some(Errno::ECONNREFUSED) => {
+            debug!("Removing stale socket file {:?}.", path);
+            fs::remove_file(path)
+        }
+        Err(err) => Err(nix_to_io_error(err, "Socket Connect Error!")),
+    }
+}
+
+/// A `sockaddr_un` built by hand: nix computes the offset of `sun_path`
+/// through a null pointer, which trips the checks of newer compilers.
+struct UnixSocketAddr {
+    addr: libc::sockaddr_un,
+    len: libc::socklen_t,
+}
+
+impl UnixSocketAddr {
+    /// Abstract names start with a NUL byte, paths end with one.
+    fn new(name: &[u8], is_abstract: bool) -> io::Result<Self> {
+        let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+        addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+
+        let (start, end) = if is_abstract {
+            (1, 1 + name.len())
+        } else {
+            (0, name.len() + 1)
+        };
+        if end > addr.sun_path.len() || (!is_abstract && name.contains(&0)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid Unix socket address!",
+            ));
+        }
+
+        for (i, byte) in name.iter().enumerate() {
+            addr.sun_path[start + i] = *byte as libc::c_char;
+        }
+
+        Ok(Self {
+            addr,
+            len: (mem::offset_of!(libc::sockaddr_un, sun_path) + end) as libc::socklen_t,
+        })
+    }
+
+    fn as_ptr(&self) -> *const libc::sockaddr {
+        &self.addr as *const libc::sockaddr_un as *const libc::sockaddr
+    }
+
+    fn bind(&self, fd: RawFd) -> nix::Result<()> {
+        Errno::result(unsafe { libc::bind(fd, self.as_ptr(), self.len) }).map(drop)
+    }
+
+    fn connect(&self, fd: RawFd) -> nix::Result<()> {
+        Errno::result(unsafe { libc::connect(fd, self.as_ptr(), self.len) }).map(drop)
+    }
+}
+
+/// `IPV6_V6ONLY`, which nix doesn't wrap.
+#[derive(Clone)]
+struct Ipv6V6Only;
+
+impl SetSockOpt for Ipv6V6Only {
+    type Val = bool;
+
+    fn set(&self, fd: RawFd, val: &bool) -> nix::Result<()> {
+        let val = *val as libc::c_int;
+        let res = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::IPPROTO_IPV6,
+                libc::IPV6_V6ONLY,
+                &val as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        Errno::result(res).map(drop)
+    }
+}
+
+/// A zero `timeval` means no timeout to the kernel, so timeouts are at
+/// least a microsecond long.
+fn to_micros(timeout: Option<Duration>) -> u64 {
+    timeout.map_or(0, |timeout| (timeout.as_micros() as u64).max(1))
+}
+
+fn to_time_val(timeout: Option<Duration>) -> TimeVal {
+    match to_micros(timeout) {
+        0 => TimeVal::zero(),
+        micros => TimeVal::microseconds(micros as i64),
+    }
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+fn from_micros(micros: u64) -> Option<Duration> {
+    match micros {
+        0 => None,
+        micros => Some(Duration::from_micros(micros)),
     }
 }
 
 fn nix_to_io_error(err: nix::Error, err_message: &'static str) -> io::Error {
     match err.as_errno() {
         Some(err_num) => io::Error::from_raw_os_error(err_num as i32),
-        None => io::Error::new(io::ErrorKind::Other, err_message),
+        None => io::Error::other(err_message),
+    }
+}
+
+#[cfg(test)]
+mod test_only_v6 {
+    use super::*;
+    use std::net::TcpStream;
+
+    /// Binds `[::]` on a free port and listens.
+    fn listen_v6(only_v6: bool) -> (Socket, u16) {
+        let addr: SocketAddr = "[::]:0".parse().unwrap();
+        let socket = Socket::new(&addr).unwrap();
+        socket.set_only_v6(only_v6).unwrap();
+        socket.bind(&addr).unwrap();
+        socket.listen(8).unwrap();
+
+        let port = socket.local_addr().unwrap().port();
+        (socket, port)
+    }
+
+    fn bind_v4(port: u16) -> io::Result<Socket> {
+        let addr = SocketAddr::from(([0, 0, 0, 0], port));
+        let socket = Socket::new(&addr)?;
+        socket.bind(&addr)?;
+        Ok(socket)
+    }
+
+    #[test]
+    fn test_dual_stack_socket_should_accept_ipv4_clients() {
+        let (socket, port) = listen_v6(false);
+
+        TcpStream::connect(("127.0.0.1", port)).unwrap();
+        TcpStream::connect(("::1", port)).unwrap();
+        let peer = socket.accept().unwrap();
+        assert!(!peer.is_unix());
+
+        let err = bind_v4(port).err().unwrap();
+        assert_eq!(io::ErrorKind::AddrInUse, err.kind());
+    }
+
+    #[test]
+    fn test_v6_only_socket_should_leave_ipv4_alone() {
+        let (_socket, port) = listen_v6(true);
+
+        TcpStream::connect(("::1", port)).unwrap();
+        let err = TcpStream::connect(("127.0.0.1", port)).unwrap_err();
+        assert_eq!(io::ErrorKind::ConnectionRefused, err.kind());
+
+        bind_v4(port).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_unix_socket {
+    use super::*;
+    use std::{env, os::unix::net::UnixStream, path::PathBuf};
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("socket_{}_{}.sock", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn listen_unix(path: &Path) -> Socket {
+        let socket = Socket::new_unix().unwrap();
+        socket.bind_unix(path).unwrap();
+        socket.listen(8).unwrap();
+        socket
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn assert_own_credentials(peer: &Socket) {
+        use nix::unistd::{getgid, getuid};
+
+        assert_eq!(
+            PeerCredentials {
+                pid: std::process::id() as i32,
+                uid: getuid().as_raw(),
+                gid: getgid().as_raw(),
+            },
+            peer.peer_credentials().unwrap()
+        );
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_bind_unix_should_report_peer_credentials() {
+        let path = socket_path("credentials");
+        let socket = listen_unix(&path);
+        assert!(socket.is_unix());
+
+        let _client = UnixStream::connect(&path).unwrap();
+        let peer = socket.accept().unwrap();
+        assert!(peer.is_unix());
+        assert_own_credentials(&peer);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_remove_stale_unix_socket_should_only_remove_dead_sockets() {
+        let path = socket_path("stale");
+        remove_stale_unix_socket(&path).unwrap();
+
+        let socket = listen_unix(&path);
+        let err = remove_stale_unix_socket(&path).unwrap_err();
+        assert_eq!(io::ErrorKind::AddrInUse, err.kind());
+        assert!(path.exists());
+
+        drop(socket);
+        assert!(path.exists());
+        remove_stale_unix_socket(&path).unwrap();
+        assert!(!path.exists());
+
+        fs::write(&path, "not a socket").unwrap();
+        let err = remove_stale_unix_socket(&path).unwrap_err();
+        assert_eq!(io::ErrorKind::AlreadyExists, err.kind());
+        assert!(path.exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_bind_abstract_should_leave_no_file_behind() {
+        use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr as UnixAddr};
+
+        let name = format!("socket_abstract_{}", std::process::id());
+        let socket = Socket::new_unix().unwrap();
+        socket.bind_abstract(name.as_bytes()).unwrap();
+        socket.listen(8).unwrap();
+
+        let err = Socket::new_unix()
+            .unwrap()
+            .bind_abstract(name.as_bytes())
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::AddrInUse, err.kind());
+
+        let addr = UnixAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let _client = UnixStream::connect_addr(&addr).unwrap();
+        assert_own_credentials(&socket.accept().unwrap());
+
+        drop(socket);
+        Socket::new_unix()
+            .unwrap()
+            .bind_abstract(name.as_bytes())
+            .unwrap();
     }
 }
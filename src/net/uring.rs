@@ -0,0 +1,131 @@
+use io_uring::{opcode, squeue, types, IoUring};
+use std::{cell::RefCell, io, os::unix::io::RawFd, ptr, time::Duration};
+
+/// Submission queue size of every ring. A call never has more than two
+/// entries in flight.
+const RING_ENTRIES: u32 = 8;
+
+const OPERATION: u64 = 0;
+const TIMEOUT: u64 = 1;
+const CANCEL: u64 = 2;
+
+// Every thread gets its own small ring on first use. A call pushes its
+// entries and waits for their completions before returning, so the buffers
+// it borrows outlive the kernel's use of them.
+thread_local! {
+    static RING: RefCell<Option<IoUring>> = const { RefCell::new(None) };
+}
+
+/// Sets up the calling thread's ring, failing if the kernel doesn't support
+/// io_uring or doesn't allow it.
+pub fn probe() -> io::Result<()> {
+    with_ring(|_| Ok(()))
+}
+
+pub fn accept(fd: RawFd) -> io::Result<RawFd> {
+    let accept = opcode::Accept::new(types::Fd(fd), ptr::null_mut(), ptr::null_mut()).build();
+    submit(accept, None)
+}
+
+/// Receives into `buf`. Fails with `io::ErrorKind::WouldBlock` when nothing
+/// arrived for `timeout`, like a socket with `SO_RCVTIMEO` set.
+pub fn receive(fd: RawFd, buf: &mut [u8], timeout: Option<Duration>) -> io::Result<usize> {
+    let recv = opcode::Recv::new(types::Fd(fd), buf.as_mut_ptr(), buf.len() as u32).build();
+    submit_timed(recv, timeout).map(|read_bytes| read_bytes as usize)
+}
+
+/// Sends from `buf`. Fails with `io::ErrorKind::WouldBlock` when the peer
+/// took nothing for `timeout`, like a socket with `SO_SNDTIMEO` set.
+pub fn send(fd: RawFd, buf: &[u8], timeout: Option<Duration>) -> io::Result<usize> {
+    let send = opcode::Send::new(types::Fd(fd), buf.as_ptr(), buf.len() as u32).build();
+    submit_timed(send, timeout).map(|sent_bytes| sent_bytes as usize)
+}
+
+/// Reads from `fd` into `buf` starting at `offset`, without moving the file
+/// position.
+pub fn read_at(fd: RawFd, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    let read = opcode::Read::new(types::Fd(fd), buf.as_mut_ptr(), buf.len() as u32)
+        .offset(offset)
+        .build();
+    submit(read, None).map(|read_bytes| read_bytes as usize)
+}
+
+/// Like `submit`, but a cancelled operation reports timing out the way
+/// socket timeouts do.
+fn submit_timed(entry: squeue::Entry, timeout: Option<Duration>) -> io::Result<i32> {
+    match submit(entry, timeout) {
+        Err(e) if e.raw_os_error() == Some(nix::libc::ECANCELED) => {
+            Err(io::Error::from(io::ErrorKind::WouldBlock))
+        }
+        result => result,
+    }
+}
+
+fn with_ring<T>(f: impl FnOnce(&mut IoUring) -> io::Result<T>) -> io::Result<T> {
+    RING.with(|ring| {
+        let mut ring = ring.borrow_mut();
+        if ring.is_none() {
+            *ring = Some(IoUring::new(RING_ENTRIES)?);
+        }
+        f(ring.as_mut().unwrap())
+    })
+}
+
+/// Runs `entry`, cancelled once `timeout` passes, and waits for it to
+/// complete. Negative results are turned into the matching OS errors.
+fn submit(entry: squeue::Entry, timeout: Option<Duration>) -> io::Result<i32> {
+    let timespec = timeout.map(types::Timespec::from);
+
+    with_ring(|ring| {
+        let mut entries = vec![entry.user_data(OPERATION)];
+        if let Some(timespec) = timespec.as_ref() {
+            entries[0] = entries[0].clone().flags(squeue::Flags::IO_LINK);
+            entries.push(
+                opcode::LinkTimeout::new(timespec)
+                    .build()
+                    .user_data(TIMEOUT),
+            );
+        }
+
+        unsafe { ring.submission().push_multiple(&entries) }
+            .map_err(|_| io::Error::other("io_uring Submission Queue Full!"))?;
+
+        // The kernel may use the borrowed buffers and timespec until every
+        // entry completed, so even a failed wait can't return early: the
+        // operation is cancelled and its completions drained first.
+        let mut pending = entries.len();
+        let mut result = 0;
+        let mut error = None;
+        while pending > 0 {
+            match ring.submit_and_wait(pending) {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if error.is_none() => {
+                    let cancel = opcode::AsyncCancel::new(OPERATION)
+                        .build()
+                        .user_data(CANCEL);
+                    if unsafe { ring.submission().push(&cancel) }.is_ok() {
+                        pending += 1;
+                    }
+                    error = Some(e);
+                }
+                Err(_) => {}
+            }
+
+            for completion in ring.completion() {
+                if completion.user_data() == OPERATION {
+                    result = completion.result();
+                }
+                pending -= 1;
+            }
+        }
+
+        if let Some(e) = error {
+            return Err(e);
+        }
+        if result < 0 {
+            return Err(io::Error::from_raw_os_error(-result));
+        }
+        Ok(result)
+    })
+}